@@ -0,0 +1,221 @@
+//! Benchmark of `sybilhunter --cluster`, over a network of 7000 relays built
+//! from the test consensus: its relays are copied, each copy with its own
+//! identity, nickname, address and weight, and some copies make groups of
+//! Sybils with a numbered nickname and close addresses.
+//!
+//! Run with `cargo bench`.
+
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+
+use margot::commands::consensus;
+use margot::commands::similarity::Metrics;
+use margot::commands::sybilhunter;
+
+use tor_netdir::NetDir;
+
+const RELAYS: usize = 7000;
+/// The number of groups of Sybils, and of relays in every group.
+const SYBILS: (usize, usize) = (30, 20);
+
+static SYLLABLES: &[&str] = &[
+    "tor", "relay", "node", "exit", "guard", "onion", "priv", "free", "net",
+    "anon", "cat", "moon", "sun", "fox", "zeb", "ra", "ka", "lo", "mi",
+];
+static PORTS: &[u16] = &[443, 9001, 9001, 9001, 8443, 9000];
+
+/// A relay of the test consensus: the lines of its router status entry and
+/// its microdescriptor.
+struct Template {
+    entry: Vec<String>,
+    microdesc: String,
+}
+
+/// What a copy of a relay has of its own.
+#[derive(Clone)]
+struct Copy {
+    nickname: String,
+    address: [u8; 4],
+    port: u16,
+    weight: u32,
+}
+
+fn testdata(name: &str) -> String {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    read_to_string(root.join("testdata").join(name)).unwrap()
+}
+
+/// The digest of a microdescriptor, as listed in the consensus.
+fn digest(microdesc: &str) -> String {
+    STANDARD_NO_PAD.encode(Sha256::digest(microdesc.as_bytes()))
+}
+
+/// Split the test consensus in its header, the relays and its footer.
+fn templates() -> (String, Vec<Template>, String) {
+    let microdescs = testdata("microdescs");
+    let mut by_digest = HashMap::new();
+    let mut microdesc = String::new();
+    for line in microdescs.lines() {
+        if line == "onion-key" && !microdesc.is_empty() {
+            by_digest.insert(digest(&microdesc), microdesc.clone());
+            microdesc.clear();
+        }
+        microdesc.push_str(line);
+        microdesc.push('\n');
+    }
+    by_digest.insert(digest(&microdesc), microdesc);
+
+    let consensus = testdata("consensus-microdesc");
+    let (header, rest) =
+        consensus.split_at(consensus.find("\nr ").unwrap() + 1);
+    let (entries, footer) =
+        rest.split_at(rest.find("directory-footer").unwrap());
+    let mut templates: Vec<Template> = Vec::new();
+    for line in entries.lines() {
+        if line.starts_with("r ") {
+            templates.push(Template {
+                entry: Vec::new(),
+                microdesc: String::new(),
+            });
+        }
+        let template = templates.last_mut().unwrap();
+        if let Some(digest) = line.strip_prefix("m ") {
+            template.microdesc = by_digest[digest].clone();
+        }
+        template.entry.push(line.to_string());
+    }
+    (header.to_string(), templates, footer.to_string())
+}
+
+/// Return a copy with a random nickname, address and weight.
+fn random_copy(rng: &mut StdRng) -> Copy {
+    let nickname: String = (0..rng.gen_range(1..4))
+        .map(|_| *SYLLABLES.choose(rng).unwrap())
+        .collect::<String>()
+        + &rng.gen_range(0..100).to_string();
+    Copy {
+        nickname,
+        address: [rng.gen_range(1..224), rng.gen(), rng.gen(), rng.gen()],
+        port: if rng.gen_bool(0.9) {
+            *PORTS.choose(rng).unwrap()
+        } else {
+            rng.gen_range(1024..65535)
+        },
+        // Log-uniform consensus weights, from 1 to 100000.
+        weight: 10f64.powf(rng.gen_range(0.0..5.0)) as u32,
+    }
+}
+
+/// Return the Rsa identity, the router status entry and the microdescriptor
+/// of a copy of the relay, with new identity keys.
+fn instantiate(
+    template: &Template,
+    copy: &Copy,
+    rng: &mut StdRng,
+) -> ([u8; 20], String, String) {
+    let rsa_id: [u8; 20] = rng.gen();
+    let ed_id: [u8; 32] = rng.gen();
+    let microdesc: String = template
+        .microdesc
+        .lines()
+        .map(|line| match line.strip_prefix("id ed25519 ") {
+            Some(_) => {
+                format!("id ed25519 {}\n", STANDARD_NO_PAD.encode(ed_id))
+            }
+            None => format!("{line}\n"),
+        })
+        .collect();
+    let [a, b, c, d] = copy.address;
+    let entry: String = template
+        .entry
+        .iter()
+        .map(|line| {
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields[0] {
+                "r" => format!(
+                    "r {} {} {} {} {a}.{b}.{c}.{d} {} 0\n",
+                    copy.nickname,
+                    STANDARD_NO_PAD.encode(rsa_id),
+                    fields[3],
+                    fields[4],
+                    copy.port,
+                ),
+                "m" => format!("m {}\n", digest(&microdesc)),
+                "w" => format!("w Bandwidth={}\n", copy.weight),
+                _ => format!("{line}\n"),
+            }
+        })
+        .collect();
+    (rsa_id, entry, microdesc)
+}
+
+/// Return the network directory of random copies of the test relays, and of
+/// groups of copies with a numbered nickname and their own address in the
+/// same /24.
+fn network() -> NetDir {
+    let (header, templates, footer) = templates();
+    let mut rng = StdRng::seed_from_u64(RELAYS as u64);
+    let mut copies: Vec<(&Template, Copy)> = (0..RELAYS - SYBILS.0 * SYBILS.1)
+        .map(|_| (templates.choose(&mut rng).unwrap(), random_copy(&mut rng)))
+        .collect();
+    for _ in 0..SYBILS.0 {
+        let template = templates.choose(&mut rng).unwrap();
+        let sybil = random_copy(&mut rng);
+        for n in 0..SYBILS.1 {
+            let mut copy = sybil.clone();
+            copy.nickname = format!("{}{:02}", copy.nickname, n);
+            copy.address[3] = n as u8 + 1;
+            copies.push((template, copy));
+        }
+    }
+    let mut relays: Vec<_> = copies
+        .iter()
+        .map(|(template, copy)| instantiate(template, copy, &mut rng))
+        .collect();
+    // The consensus lists the relays by identity.
+    relays.sort_by_key(|relay| relay.0);
+
+    let mut consensus = header;
+    let mut microdescs = String::new();
+    for (_, entry, microdesc) in &relays {
+        consensus.push_str(entry);
+        microdescs.push_str(microdesc);
+    }
+    consensus.push_str(&footer);
+    let consensus = consensus::parse_consensus(&consensus).unwrap();
+    consensus::build_netdir(consensus, &[microdescs]).unwrap()
+}
+
+fn lsh_clusters(c: &mut Criterion) {
+    let netdir = network();
+    let relays: Vec<_> = netdir.relays().collect();
+    assert_eq!(relays.len(), RELAYS);
+    let metrics: Metrics =
+        "nickname:jw:2,address,flags,version,weight,family,policy4"
+            .parse()
+            .unwrap();
+
+    let mut group = c.benchmark_group("sybilhunter");
+    group.sample_size(10);
+    group.bench_function("lsh clusters of 7000 relays", |b| {
+        b.iter(|| sybilhunter::cluster(&relays, None, None, false, 3.0, 2))
+    });
+    group.bench_function("lsh clusters of 7000 relays with metrics", |b| {
+        b.iter(|| {
+            let metrics = Some(metrics.clone());
+            sybilhunter::cluster(&relays, metrics, None, false, 0.1, 2)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, lsh_clusters);
+criterion_main!(benches);
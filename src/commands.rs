@@ -1,12 +1,31 @@
+pub mod asn;
+pub mod collector;
 mod config;
+mod confusables;
+pub mod consensus;
+mod contact;
 mod count;
+pub mod descriptors;
+mod diff;
 mod err;
+mod expr;
 mod find;
+pub mod geoip;
+mod group;
+pub mod history;
+mod ingest;
 mod like;
+mod lsh;
+mod output;
+pub mod similarity;
 mod sybil;
-mod sybilhunter;
+pub mod sybilhunter;
 mod test;
 mod util;
+mod vanity;
+mod version;
+mod watch;
+mod weight;
 
 use anyhow::Result;
 use async_trait::async_trait;
@@ -40,8 +59,15 @@ pub enum SubCommand {
     Config(config::ConfigCommand),
     #[structopt(name = "count", about = "Count relay(s) in the consensus")]
     Count(count::CountCommand),
+    #[structopt(name = "diff", about = "Compare two consensuses")]
+    Diff(diff::DiffCommand),
     #[structopt(name = "find", about = "Find relay(s) in the consensus")]
     Find(find::FindCommand),
+    #[structopt(
+        name = "ingest",
+        about = "Record the relays of consensus(es) in the history database"
+    )]
+    Ingest(ingest::IngestCommand),
     #[structopt(
         name = "like",
         about = "Match alike relay(s) in the consensus"
@@ -56,18 +82,66 @@ pub enum SubCommand {
         about = "Discover Sybil relays which are configured in a similar way"
     )]
     SybilHunter(sybilhunter::SybilHunterCommand),
+    #[structopt(
+        name = "vanity",
+        about = "Find relays sharing a fingerprint prefix beyond chance"
+    )]
+    Vanity(vanity::VanityCommand),
+    #[structopt(
+        name = "watch",
+        about = "Watch the relays matching filters in every new consensus"
+    )]
+    Watch(watch::WatchCommand),
 }
 
 impl SubCommand {
+    /// Return the command if it can run without a connection to the Tor
+    /// network.
+    fn cmd_offline(&self) -> Option<&dyn RunnableOffline> {
+        match self {
+            SubCommand::Config(c) => Some(c),
+            SubCommand::Count(c) => Some(c),
+            SubCommand::Diff(c) => Some(c),
+            SubCommand::Find(c) => Some(c),
+            SubCommand::Ingest(c) => Some(c),
+            SubCommand::Like(c) => Some(c),
+            SubCommand::Sybil(c) => Some(c),
+            SubCommand::Test(_) => None,
+            SubCommand::SybilHunter(c) => Some(c),
+            SubCommand::Vanity(c) => Some(c),
+            SubCommand::Watch(_) => None,
+        }
+    }
+
+    /// Run the command if it doesn't need any network directory.
+    pub fn run_standalone(&self) -> Option<Result<()>> {
+        match self {
+            SubCommand::Ingest(c) => c.run_archives(),
+            _ => None,
+        }
+    }
+
+    /// Run the command on the given [NetDir](tor_netdir::NetDir) instead of
+    /// the one from arti.
+    pub fn run_offline(&self, netdir: &tor_netdir::NetDir) -> Result<()> {
+        self.cmd_offline()
+            .ok_or(err::Error::NetworkRequired)?
+            .run(netdir)
+    }
+
     fn cmd<R: Runtime>(&self) -> &(dyn Runnable<R> + Send + Sync) {
         match self {
             SubCommand::Config(c) => c,
             SubCommand::Count(c) => c,
+            SubCommand::Diff(c) => c,
             SubCommand::Find(c) => c,
+            SubCommand::Ingest(c) => c,
             SubCommand::Like(c) => c,
             SubCommand::Sybil(c) => c,
             SubCommand::Test(c) => c,
             SubCommand::SybilHunter(c) => c,
+            SubCommand::Vanity(c) => c,
+            SubCommand::Watch(c) => c,
         }
     }
 }
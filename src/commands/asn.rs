@@ -0,0 +1,176 @@
+//! Autonomous System lookups using an IP to ASN database.
+//!
+//! The database is a TSV file as published by <https://iptoasn.com>, eg.
+//! `ip2asn-combined.tsv`, with the lines:
+//! `<first address>\t<last address>\t<ASN>\t<country>\t<AS description>`.
+//! The ranges that aren't routed have the ASN 0 and are skipped.
+//!
+//! It's loaded from a local file, once and only when needed.
+
+use std::fmt;
+use std::fs::read_to_string;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::commands::err::Error;
+use crate::commands::geoip;
+
+static PATH: OnceLock<PathBuf> = OnceLock::new();
+static ASNDB: OnceLock<AsnDb> = OnceLock::new();
+
+/// An Autonomous System.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct As {
+    pub number: u32,
+    pub name: String,
+}
+
+impl fmt::Display for As {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AS{} {}", self.number, self.name)
+    }
+}
+
+/// Address ranges, sorted by their lowest address, and their AS.
+#[derive(Debug, Default)]
+pub struct AsnDb {
+    v4: Vec<(u32, u32, As)>,
+    v6: Vec<(u128, u128, As)>,
+}
+
+/// Parse an ASN, with or without the `AS` prefix.
+pub fn parse_asn(s: &str) -> Result<u32, Error> {
+    let number = s
+        .strip_prefix("AS")
+        .or_else(|| s.strip_prefix("as"))
+        .unwrap_or(s);
+    number
+        .parse()
+        .map_err(|_| Error::InvalidFilter(format!("Invalid ASN: {s}")))
+}
+
+impl AsnDb {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let mut db = AsnDb::default();
+        for line in read_to_string(path)?.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = || Error::InvalidDatabase(line.to_string());
+            let fields: Vec<_> = line.splitn(5, '\t').collect();
+            let [first, last, number, _, name] = fields[..] else {
+                return Err(invalid());
+            };
+            let number: u32 = number.parse().map_err(|_| invalid())?;
+            if number == 0 {
+                continue;
+            }
+            let asys = As {
+                number,
+                name: name.to_string(),
+            };
+            match (first.parse(), last.parse()) {
+                (Ok(IpAddr::V4(first)), Ok(IpAddr::V4(last))) => {
+                    db.v4.push((first.into(), last.into(), asys))
+                }
+                (Ok(IpAddr::V6(first)), Ok(IpAddr::V6(last))) => {
+                    db.v6.push((first.into(), last.into(), asys))
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        db.v4.sort_by_key(|(first, _, _)| *first);
+        db.v6.sort_by_key(|(first, _, _)| *first);
+        Ok(db)
+    }
+
+    /// Return the AS of the address, if it's in the database.
+    pub fn lookup(&self, ip: IpAddr) -> Option<&As> {
+        match ip {
+            IpAddr::V4(ip) => geoip::lookup(&self.v4, u32::from(ip)),
+            IpAddr::V6(ip) => geoip::lookup(&self.v6, u128::from(ip)),
+        }
+    }
+}
+
+/// Set the path of the database.
+pub fn configure(path: &Path) {
+    // Only the first configuration is kept.
+    let _ = PATH.set(path.to_path_buf());
+}
+
+/// Whether a database was given.
+pub fn is_configured() -> bool {
+    PATH.get().is_some()
+}
+
+/// Load the database from the configured path, if not already loaded.
+pub fn load() -> Result<&'static AsnDb, Error> {
+    if let Some(db) = ASNDB.get() {
+        return Ok(db);
+    }
+    let path = PATH
+        .get()
+        .ok_or_else(|| Error::MissingDatabase("ASN".to_string()))?;
+    let loaded = AsnDb::load(path)?;
+    Ok(ASNDB.get_or_init(|| loaded))
+}
+
+/// Return the database if it's loaded.
+pub fn get() -> Option<&'static AsnDb> {
+    ASNDB.get()
+}
+
+/// Return the Autonomous Systems of the relay's ORPort addresses.
+pub fn relay_ases(relay: &tor_netdir::Relay) -> Vec<&'static As> {
+    let mut ases: Vec<_> = relay
+        .rs()
+        .orport_addrs()
+        .filter_map(|addr| get()?.lookup(addr.ip()))
+        .collect();
+    ases.sort();
+    ases.dedup();
+    ases
+}
+
+/// Load the database in `testdata/`, for tests.
+#[cfg(test)]
+pub fn configure_testdata() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    configure(&root.join("testdata/ip2asn.tsv"));
+    load().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_ases() {
+        configure_testdata();
+        let db = load().unwrap();
+        let number =
+            |ip: &str| db.lookup(ip.parse().unwrap()).map(|a| a.number);
+        assert_eq!(number("192.0.2.20"), Some(64496));
+        assert_eq!(number("198.51.100.255"), Some(64497));
+        assert_eq!(number("2001:db8::1"), Some(64497));
+        assert_eq!(number("203.0.114.1"), None);
+        // Not routed.
+        assert_eq!(number("1.0.0.1"), None);
+        assert_eq!(
+            db.lookup("203.0.113.10".parse().unwrap())
+                .unwrap()
+                .to_string(),
+            "AS64498 GUARDNET"
+        );
+    }
+
+    #[test]
+    fn parse_asns() {
+        assert_eq!(parse_asn("AS64496").unwrap(), 64496);
+        assert_eq!(parse_asn("as64496").unwrap(), 64496);
+        assert_eq!(parse_asn("64496").unwrap(), 64496);
+        assert!(parse_asn("ASN64496").is_err());
+    }
+}
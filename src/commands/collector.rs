@@ -0,0 +1,318 @@
+//! Read CollecTor archives of microdescriptor consensuses.
+//!
+//! An archive is either a directory or a tarball, optionally compressed with
+//! xz, laid out as in
+//! <https://collector.torproject.org/archive/relay-descriptors/microdescs/>:
+//! the consensuses are named after their valid-after time, eg.
+//! `2023-05-01-12-00-00-consensus-microdesc`, and the microdescriptors after
+//! their hex digest.
+
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use xz2::read::XzDecoder;
+
+use crate::commands::consensus;
+use crate::commands::err::Error;
+
+use tor_netdir::NetDir;
+use tor_netdoc::doc::netstatus::MdConsensus;
+
+static CONSENSUS_SUFFIX: &str = "-consensus-microdesc";
+/// Suffix of the files concatenating many microdescriptors, as in the
+/// `recent` CollecTor directory.
+static MICRODESCS_SUFFIX: &str = "-micro";
+
+fn file_name(path: &Path) -> Option<&str> {
+    path.file_name().and_then(|name| name.to_str())
+}
+
+/// Return the valid-after time encoded in a consensus file name.
+fn valid_after(name: &str) -> Option<SystemTime> {
+    let date = name.strip_suffix(CONSENSUS_SUFFIX)?;
+    // `YYYY-MM-DD-HH-MM-SS` to `YYYY-MM-DD HH:MM:SS`
+    let day = date.get(..10)?;
+    let time = date.get(11..)?.replace('-', ":");
+    humantime::parse_rfc3339_weak(&format!("{} {}", day, time)).ok()
+}
+
+/// A directory or a tarball of CollecTor files.
+pub struct Archive {
+    path: PathBuf,
+}
+
+impl Archive {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// Call `f` with the path and a reader of every file in the archive.
+    pub fn walk<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&Path, &mut dyn Read) -> Result<()>,
+    {
+        if self.path.is_dir() {
+            let mut dirs = vec![self.path.clone()];
+            while let Some(dir) = dirs.pop() {
+                for entry in fs::read_dir(dir)? {
+                    let path = entry?.path();
+                    if path.is_dir() {
+                        dirs.push(path);
+                    } else {
+                        f(&path, &mut File::open(&path)?)?;
+                    }
+                }
+            }
+            return Ok(());
+        }
+        let file = File::open(&self.path)?;
+        let reader: Box<dyn Read> =
+            if self.path.extension().is_some_and(|ext| ext == "xz") {
+                Box::new(XzDecoder::new(file))
+            } else {
+                Box::new(file)
+            };
+        let mut tarball = tar::Archive::new(reader);
+        for entry in tarball.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            f(&path, &mut entry)?;
+        }
+        Ok(())
+    }
+
+    /// Read the wanted consensuses and all the microdescriptors in a single
+    /// walk of the archive. The files which can't be read are skipped.
+    fn read(&self, wanted: Consensuses) -> Result<Documents> {
+        let mut documents = Documents::default();
+        self.walk(|path, reader| {
+            let Some(name) = file_name(path) else {
+                return Ok(());
+            };
+            let time = valid_after(name);
+            let keep = match (&wanted, time) {
+                (Consensuses::Latest(at), Some(time)) => {
+                    at.is_none_or(|at| time <= at)
+                        && documents
+                            .consensuses
+                            .last_key_value()
+                            .is_none_or(|(t, _)| time > *t)
+                }
+                (Consensuses::All, Some(_)) => true,
+                (_, None) => {
+                    name.ends_with(MICRODESCS_SUFFIX) || is_digest(name)
+                }
+            };
+            if !keep {
+                return Ok(());
+            }
+            let mut text = String::new();
+            if let Err(err) = reader.read_to_string(&mut text) {
+                println!("[-] Skipping {}: {}", path.display(), err);
+                return Ok(());
+            }
+            match time {
+                Some(time) => {
+                    if matches!(wanted, Consensuses::Latest(_)) {
+                        documents.consensuses.clear();
+                    }
+                    documents.consensuses.insert(time, text);
+                }
+                None if is_digest(name) => {
+                    documents.microdescs.insert(name.to_string(), text);
+                }
+                None => documents.bundles.push(text),
+            }
+            Ok(())
+        })?;
+        Ok(documents)
+    }
+}
+
+/// Whether the file name is the hex digest of a microdescriptor.
+fn is_digest(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// The consensuses read from an archive.
+enum Consensuses {
+    /// The most recent one which became valid before the time, or the most
+    /// recent one if there is no time.
+    Latest(Option<SystemTime>),
+    All,
+}
+
+/// The documents read from an archive.
+#[derive(Default)]
+struct Documents {
+    /// The text of the consensuses, by valid-after time. They're all kept
+    /// until the end of the walk, as the microdescriptors may come after
+    /// them.
+    consensuses: BTreeMap<SystemTime, String>,
+    /// The text of the microdescriptors, by hex digest.
+    microdescs: HashMap<String, String>,
+    /// The text of the files of many microdescriptors.
+    bundles: Vec<String>,
+}
+
+impl Documents {
+    /// Build a [NetDir] from the consensus, with only the microdescriptors
+    /// it lists.
+    fn build_netdir(&self, consensus: MdConsensus) -> Result<NetDir> {
+        let mut microdescs: Vec<&str> = consensus
+            .relays()
+            .iter()
+            .filter_map(|rs| self.microdescs.get(&hex::encode(rs.md_digest())))
+            .map(String::as_str)
+            .collect();
+        microdescs.extend(self.bundles.iter().map(String::as_str));
+        consensus::build_netdir(consensus, &microdescs)
+    }
+}
+
+/// Load a [NetDir] from the consensus in a CollecTor archive that was valid
+/// at `at`, or from the most recent one if there is no `at`.
+pub fn load_netdir(path: &Path, at: Option<SystemTime>) -> Result<NetDir> {
+    let documents = Archive::new(path).read(Consensuses::Latest(at))?;
+    let (_, text) = documents
+        .consensuses
+        .last_key_value()
+        .ok_or_else(|| Error::NoConsensus(path.display().to_string()))?;
+    let consensus = consensus::parse_consensus(text)?;
+    if at.is_some_and(|at| at >= consensus.lifetime().valid_until()) {
+        return Err(Error::NoConsensus(path.display().to_string()).into());
+    }
+    documents.build_netdir(consensus)
+}
+
+/// Call `f` with the [NetDir] of every consensus in a CollecTor archive,
+/// from the oldest to the most recent, reading the archive only once. The
+/// consensuses which can't be loaded are skipped.
+pub fn for_each_netdir<F>(path: &Path, mut f: F) -> Result<()>
+where
+    F: FnMut(NetDir) -> Result<()>,
+{
+    let documents = Archive::new(path).read(Consensuses::All)?;
+    for (time, text) in &documents.consensuses {
+        let netdir = consensus::parse_consensus(text)
+            .and_then(|consensus| documents.build_netdir(consensus));
+        match netdir {
+            Ok(netdir) => f(netdir)?,
+            Err(err) => println!(
+                "[-] Skipping consensus {}: {}",
+                humantime::format_rfc3339_seconds(*time),
+                err
+            ),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+    }
+
+    fn time(s: &str) -> Option<SystemTime> {
+        Some(humantime::parse_rfc3339_weak(s).unwrap())
+    }
+
+    #[test]
+    fn valid_after_from_file_name() {
+        assert_eq!(
+            valid_after("2023-05-01-12-00-00-consensus-microdesc"),
+            time("2023-05-01 12:00:00")
+        );
+        assert_eq!(valid_after("2023-05-01-12-00-00-micro"), None);
+    }
+
+    #[test]
+    fn load_netdir_from_directory() {
+        let path = root().join("testdata/collector");
+        let netdir = load_netdir(&path, time("2023-05-01 12:30:00")).unwrap();
+        assert_eq!(netdir.relays().count(), 7);
+        // The most recent consensus doesn't list `BadOne` anymore.
+        let netdir = load_netdir(&path, None).unwrap();
+        assert_eq!(netdir.relays().count(), 6);
+    }
+
+    #[test]
+    fn load_netdir_from_tarball() {
+        let path = root().join("testdata/microdescs-2023-05.tar.xz");
+        let netdir = load_netdir(&path, time("2023-05-01 12:30:00")).unwrap();
+        assert_eq!(netdir.relays().count(), 7);
+        let netdir = load_netdir(&path, time("2023-05-01 15:30:00")).unwrap();
+        assert_eq!(netdir.relays().count(), 6);
+    }
+
+    #[test]
+    fn every_netdir_of_archive() {
+        let path = root().join("testdata/microdescs-2023-05.tar.xz");
+        let mut netdirs = Vec::new();
+        for_each_netdir(&path, |netdir| {
+            netdirs.push((
+                netdir.lifetime().valid_after(),
+                netdir.relays().count(),
+            ));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            netdirs,
+            [
+                (time("2023-05-01 12:00:00").unwrap(), 7),
+                (time("2023-05-01 13:00:00").unwrap(), 6)
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_invalid_consensus() {
+        let dir = std::env::temp_dir()
+            .join(format!("margot-collector-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        copy_dir(&root().join("testdata/collector"), &dir);
+        fs::write(
+            dir.join("2023-05-01-14-00-00-consensus-microdesc"),
+            "not a consensus",
+        )
+        .unwrap();
+        let mut count = 0;
+        for_each_netdir(&dir, |_| {
+            count += 1;
+            Ok(())
+        })
+        .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(count, 2);
+    }
+
+    fn copy_dir(from: &Path, to: &Path) {
+        fs::create_dir_all(to).unwrap();
+        for entry in fs::read_dir(from).unwrap() {
+            let path = entry.unwrap().path();
+            let target = to.join(path.file_name().unwrap());
+            if path.is_dir() {
+                copy_dir(&path, &target);
+            } else {
+                fs::copy(&path, &target).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn load_netdir_no_consensus() {
+        let path = root().join("testdata/collector");
+        assert!(load_netdir(&path, time("2023-04-30 12:00:00")).is_err());
+        assert!(load_netdir(&path, time("2023-05-02 12:00:00")).is_err());
+    }
+}
@@ -9,8 +9,10 @@ use std::path;
 use structopt::StructOpt;
 
 use crate::commands::err::Error;
+use crate::commands::expr::{FilterArg, FilterExpr};
 use crate::commands::find;
 use crate::commands::util;
+use crate::commands::weight::Totals;
 use crate::commands::RunnableOffline;
 
 static GITLAB_BUG_URL: &str =
@@ -28,8 +30,8 @@ static BAD_PATH: &str = "torrc.d/bad.conf";
 #[derive(Debug, Clone, StructOpt)]
 pub struct BadCommand {
     ticket: u32,
-    /// The filters of this command.
-    filters: Vec<find::FindFilter>,
+    /// The filter expression of this command.
+    filters: Vec<FilterArg>,
 }
 
 #[derive(StructOpt, Debug)]
@@ -161,7 +163,8 @@ impl BadCommand {
         netdir: &tor_netdir::NetDir,
         tokens: &'static (&str, &str),
     ) -> Result<()> {
-        let relays = find::FindCommand::new(&self.filters).filter(netdir);
+        let expr = FilterExpr::parse(&self.filters)?;
+        let relays = expr.filter(netdir, &Totals::new(netdir));
 
         // Do not create bad.conf config when there is not token for it, as it
         // is the case for `middleonly` argument or when no relays were found.
@@ -201,7 +204,7 @@ impl BadCommand {
             }
             self.print_footer();
         }
-        println!("[+] Found {} relays: {:?}", relays.len(), self.filters);
+        println!("[+] Found {} relays: {}", relays.len(), expr);
         Ok(())
     }
 
@@ -214,7 +217,7 @@ impl BadCommand {
         // NOTE: The following iterations and loops could be less verbose
         // Create a vector with the missing fps from the previous vector.
         let mut fps = Vec::new();
-        for find_filter in &self.filters {
+        for find_filter in self.filters.iter().flat_map(FilterArg::filters) {
             if let find::Filter::FpsFileFilter(relay_fingerprints) =
                 &find_filter.filter
             {
@@ -261,7 +264,8 @@ mod tests {
             filters: Vec::from([find::FindFilter::new(
                 false,
                 find::Filter::Nickname("moria1".to_string()),
-            )]),
+            )
+            .into()]),
         };
         let file = bad_command.open_file(fname);
         assert!(file.is_ok());
@@ -280,7 +284,8 @@ mod tests {
             filters: Vec::from([find::FindFilter::new(
                 false,
                 find::Filter::Nickname("moria1".to_string()),
-            )]),
+            )
+            .into()]),
         };
         let mut file = bad_command.open_file(fname).unwrap();
 
@@ -297,7 +302,8 @@ mod tests {
             filters: Vec::from([find::FindFilter::new(
                 false,
                 find::Filter::FpsFileFilter(util::fpfile2fps(&path).unwrap()),
-            )]),
+            )
+            .into()]),
         };
         let found_fps =
             vec!["0011BD2485AD45D984EC4159C88FC066E5E3300E".to_string()];
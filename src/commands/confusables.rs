@@ -0,0 +1,205 @@
+//! Lookalike nicknames, to find relays impersonating well-known ones.
+//!
+//! Nicknames are compared through their skeleton, where the characters that
+//! look alike, eg. `0` and `o` or `1`, `l` and `I`, are replaced by the same
+//! one, so `m0ria1` and `moria1` have the same skeleton.
+
+use levenshtein::levenshtein;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::commands::err::Error;
+use crate::commands::util;
+
+use tor_llcrypto::pk::rsa::RsaIdentity;
+use tor_netdir::NetDir;
+use tor_netdoc::doc::netstatus::RelayFlags;
+
+/// The nicknames of the directory authorities.
+pub static AUTHORITIES: &[&str] = &[
+    "moria1",
+    "tor26",
+    "dizum",
+    "gabelmoo",
+    "dannenberg",
+    "maatuska",
+    "longclaw",
+    "bastet",
+    "faravahar",
+];
+
+/// Sequences of characters looking like a single one, replaced before the
+/// single characters.
+static SEQUENCES: &[(&str, &str)] = &[("rn", "m"), ("vv", "w"), ("cl", "d")];
+
+/// Return the character that `c`, already in lowercase, looks like.
+fn lookalike(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' | 'i' | '|' | '!' => 'l',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '6' => 'b',
+        '7' => 't',
+        '8' => 'b',
+        '9' => 'g',
+        c => c,
+    }
+}
+
+/// Return the skeleton of the nickname: in lowercase, with the lookalike
+/// characters replaced.
+pub fn skeleton(nickname: &str) -> String {
+    let mut s = nickname.to_lowercase();
+    for (sequence, c) in SEQUENCES {
+        s = s.replace(sequence, c);
+    }
+    s.chars().map(lookalike).collect()
+}
+
+/// The shortest part of a nickname before its numbering that's compared
+/// without it: the numbering of shorter nicknames, as `tor26`, tells them
+/// apart.
+const MIN_STEM: usize = 4;
+
+/// Remove the numbering of a nickname, eg. `relay` for `relay01`, unless
+/// what's left is shorter than [MIN_STEM].
+pub fn strip_numbering(nickname: &str) -> &str {
+    let stripped = nickname.trim_end_matches(|c: char| c.is_ascii_digit());
+    if stripped.len() < MIN_STEM {
+        nickname
+    } else {
+        stripped
+    }
+}
+
+/// Return the Levenshtein distance of the skeletons of the nicknames, with
+/// or without their numbering, whichever is the smallest.
+pub fn distance(a: &str, b: &str) -> usize {
+    let numbered = levenshtein(&skeleton(a), &skeleton(b));
+    let unnumbered = levenshtein(
+        &skeleton(strip_numbering(a)),
+        &skeleton(strip_numbering(b)),
+    );
+    numbered.min(unnumbered)
+}
+
+/// A well-known relay, which may be impersonated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Known {
+    pub nickname: String,
+    /// Its fingerprint, when known: the relays with the same nickname but
+    /// another fingerprint are impersonating it.
+    pub rsa_id: Option<RsaIdentity>,
+}
+
+/// Return the directory authorities, with the fingerprints of the ones in
+/// the consensus.
+pub fn authorities(netdir: &NetDir) -> Vec<Known> {
+    let mut known: Vec<Known> = netdir
+        .relays()
+        .filter(|r| r.rs().flags().contains(RelayFlags::AUTHORITY))
+        .map(|r| Known {
+            nickname: r.rs().nickname().to_string(),
+            rsa_id: Some(*r.rsa_id()),
+        })
+        .collect();
+    for nickname in AUTHORITIES {
+        if !known
+            .iter()
+            .any(|k| k.nickname.eq_ignore_ascii_case(nickname))
+        {
+            known.push(Known {
+                nickname: nickname.to_string(),
+                rsa_id: None,
+            });
+        }
+    }
+    known
+}
+
+/// Read well-known relays from a file, one `<nickname> [fingerprint]` per
+/// line. Empty lines and lines starting with `#` are ignored.
+pub fn known_from_file(path: &Path) -> Result<Vec<Known>, Error> {
+    read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut parts = line.split_whitespace();
+            let nickname = parts.next().unwrap_or_default().to_string();
+            let rsa_id = parts.next().map(util::parse_rsa_id).transpose()?;
+            Ok(Known { nickname, rsa_id })
+        })
+        .collect()
+}
+
+impl Known {
+    /// Whether the relay looks like impersonating this one: its nickname is
+    /// within `max_distance` of this one's, and it's not this relay. When
+    /// the fingerprint isn't known, the relays with this nickname, in any
+    /// case, are assumed to be this one.
+    pub fn is_impersonated_by(
+        &self,
+        nickname: &str,
+        rsa_id: &RsaIdentity,
+        max_distance: usize,
+    ) -> bool {
+        let itself = match &self.rsa_id {
+            Some(id) => id == rsa_id,
+            None => nickname.eq_ignore_ascii_case(&self.nickname),
+        };
+        !itself && distance(&self.nickname, nickname) <= max_distance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookalike_skeletons() {
+        assert_eq!(skeleton("m0ria1"), skeleton("moria1"));
+        assert_eq!(skeleton("rnoria1"), skeleton("moria1"));
+        assert_eq!(skeleton("Quintex1"), skeleton("QuintexI"));
+        assert_ne!(skeleton("moria1"), skeleton("maria1"));
+    }
+
+    #[test]
+    fn lookalike_distances() {
+        assert_eq!(distance("m0ria1", "moria1"), 0);
+        assert_eq!(distance("moria2", "moria1"), 0);
+        assert_eq!(distance("relay01", "Relay12"), 0);
+        assert_eq!(distance("gabe1moo", "gabelmoo"), 0);
+        assert!(distance("ExitOne", "moria1") > 3);
+        // tor26 isn't tor.
+        assert_eq!(strip_numbering("tor26"), "tor26");
+        assert!(distance("tor1", "tor26") > 0);
+    }
+
+    #[test]
+    fn impersonation() {
+        let id = |b| RsaIdentity::from_bytes(&[b; 20]).unwrap();
+        let moria = Known {
+            nickname: "moria1".to_string(),
+            rsa_id: Some(id(1)),
+        };
+        assert!(moria.is_impersonated_by("moria1", &id(2), 0));
+        assert!(moria.is_impersonated_by("m0ria1", &id(2), 0));
+        assert!(!moria.is_impersonated_by("moria1", &id(1), 0));
+        let dizum = Known {
+            nickname: "dizum".to_string(),
+            rsa_id: None,
+        };
+        assert!(!dizum.is_impersonated_by("dizum", &id(3), 0));
+        assert!(!dizum.is_impersonated_by("Dizum", &id(3), 0));
+        assert!(dizum.is_impersonated_by("d1zum", &id(3), 0));
+        let tor26 = Known {
+            nickname: "tor26".to_string(),
+            rsa_id: Some(id(4)),
+        };
+        assert!(!tor26.is_impersonated_by("tor1", &id(5), 0));
+        assert!(tor26.is_impersonated_by("t0r26", &id(5), 0));
+    }
+}
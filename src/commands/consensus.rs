@@ -0,0 +1,115 @@
+//! Build a network directory from documents on disk.
+//!
+//! This lets the offline commands run against archived or captured
+//! consensuses without bootstrapping arti.
+
+use anyhow::Result;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::commands::err::Error;
+
+use tor_checkable::{ExternallySigned, Timebound};
+use tor_netdir::{MdReceiver, NetDir, PartialNetDir};
+use tor_netdoc::doc::microdesc::MicrodescReader;
+use tor_netdoc::doc::netstatus::MdConsensus;
+use tor_netdoc::AllowAnnotations;
+
+/// CollecTor prepends an `@type` annotation to every document, which arti
+/// does not expect in a consensus, so remove it if present.
+fn strip_type_annotation(text: &str) -> &str {
+    match text.strip_prefix("@type ") {
+        Some(rest) => rest.split_once('\n').map_or("", |(_, doc)| doc),
+        None => text,
+    }
+}
+
+/// Parse a microdescriptor consensus.
+///
+/// Neither the signatures nor the validity period are checked: we don't
+/// have the authority certificates at hand and the whole point is to
+/// inspect consensuses that may be long expired.
+pub fn parse_consensus(text: &str) -> Result<MdConsensus> {
+    let (_, _, consensus) = MdConsensus::parse(strip_type_annotation(text))?;
+    Ok(consensus
+        .dangerously_assume_timely()
+        .dangerously_assume_wellsigned())
+}
+
+/// Build a [NetDir] from a consensus and the text of one or many
+/// microdescriptor documents.
+///
+/// Microdescriptors that aren't listed in the consensus are ignored. It
+/// fails when there are not enough of them to build usable paths.
+pub fn build_netdir<S: AsRef<str>>(
+    consensus: MdConsensus,
+    microdescs: &[S],
+) -> Result<NetDir> {
+    let mut partial = PartialNetDir::new(consensus, None);
+    for text in microdescs {
+        let reader = MicrodescReader::new(
+            strip_type_annotation(text.as_ref()),
+            &AllowAnnotations::AnnotationsAllowed,
+        );
+        for md in reader.flatten() {
+            partial.add_microdesc(md.into_microdesc());
+        }
+    }
+    let netdir = partial
+        .unwrap_if_sufficient()
+        .map_err(|p| Error::InsufficientDirectory(p.n_missing()))?;
+    Ok(netdir)
+}
+
+/// Load a [NetDir] from a consensus file and a microdescriptors file.
+pub fn load_netdir(consensus: &Path, microdescs: &Path) -> Result<NetDir> {
+    let consensus = parse_consensus(&read_to_string(consensus)?)?;
+    build_netdir(consensus, &[read_to_string(microdescs)?])
+}
+
+/// The [NetDir] built from the fixtures in `testdata/`, for tests.
+#[cfg(test)]
+pub fn test_netdir() -> NetDir {
+    let root = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    load_netdir(
+        &root.join("testdata/consensus-microdesc"),
+        &root.join("testdata/microdescs"),
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_collector_annotation() {
+        let text = "@type network-status-microdesc-consensus-3 1.0\n\
+                    network-status-version 3 microdesc\n";
+        assert_eq!(
+            strip_type_annotation(text),
+            "network-status-version 3 microdesc\n"
+        );
+        assert_eq!(strip_type_annotation("onion-key\n"), "onion-key\n");
+    }
+
+    #[test]
+    fn load_netdir_from_files() {
+        let netdir = test_netdir();
+        assert_eq!(netdir.relays().count(), 7);
+        assert!(netdir
+            .relays()
+            .any(|r| r.rs().nickname() == "nestor00patof"));
+    }
+
+    #[test]
+    #[should_panic(expected = "No such file or directory")]
+    fn load_netdir_unexisting() {
+        let root = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        load_netdir(
+            &root.join("testdata/unexisting"),
+            &root.join("testdata/microdescs"),
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+//! The ContactInfo of the relays, from their server descriptors.
+//!
+//! Contacts following the
+//! [ContactInfo Information Sharing Specification](https://nusenu.github.io/ContactInfo-Information-Sharing-Specification/)
+//! (CIISS) are made of space separated `key:value` fields, eg.
+//! `email:admin[]example.org url:https://example.org ciissversion:2`, the
+//! `ciissversion` field telling them apart from free-form contacts.
+
+use crate::commands::descriptors;
+
+use tor_netdir::Relay;
+
+/// The fields of a CIISS contact.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContactInfo {
+    fields: Vec<(String, String)>,
+}
+
+impl ContactInfo {
+    /// Parse the CIISS fields of a contact, which has none if it doesn't
+    /// follow the specification.
+    pub fn parse(contact: &str) -> Self {
+        let fields: Vec<_> = contact
+            .split_whitespace()
+            .filter_map(|field| {
+                let (key, value) = field.split_once(':')?;
+                key.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    .then(|| (key.to_lowercase(), value.to_string()))
+            })
+            .collect();
+        if !fields.iter().any(|(key, _)| key == "ciissversion") {
+            return Self::default();
+        }
+        Self { fields }
+    }
+
+    /// Whether the contact follows the specification.
+    pub fn is_ciiss(&self) -> bool {
+        !self.fields.is_empty()
+    }
+
+    /// Return the value of the first field with this key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Return the email address, with the `[]` the specification uses
+    /// instead of `@` replaced.
+    pub fn email(&self) -> Option<String> {
+        self.get("email").map(|email| email.replace("[]", "@"))
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        self.get("url")
+    }
+
+    /// Return the proof type of the url, `uri-rsa` or `dns-rsa`.
+    pub fn proof(&self) -> Option<&str> {
+        self.get("proof")
+    }
+
+    /// Whether the key matches, case insensitive, and the value contains
+    /// `value`, if any. The email is matched once deobfuscated.
+    pub fn matches(&self, key: &str, value: Option<&str>) -> bool {
+        self.fields.iter().any(|(k, v)| {
+            k == key
+                && value.is_none_or(|value| {
+                    let v = if k == "email" {
+                        v.replace("[]", "@")
+                    } else {
+                        v.clone()
+                    };
+                    v.to_lowercase().contains(value)
+                })
+        })
+    }
+}
+
+/// Return the contact of the relay descriptor, if any.
+pub fn relay_contact(relay: &Relay) -> Option<&'static str> {
+    descriptors::relay_descriptor(relay)?.contact.as_deref()
+}
+
+/// Return the CIISS fields of the relay contact.
+pub fn relay_contact_info(relay: &Relay) -> ContactInfo {
+    relay_contact(relay).map_or_else(ContactInfo::default, ContactInfo::parse)
+}
+
+/// Return the key identifying the operator of the relay from its contact:
+/// the CIISS email or url, or else the whole contact.
+pub fn operator(relay: &Relay) -> Option<String> {
+    let contact = relay_contact(relay)?;
+    let info = ContactInfo::parse(contact);
+    info.email()
+        .or_else(|| info.url().map(String::from))
+        .or_else(|| Some(contact.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ciiss() {
+        let info = ContactInfo::parse(
+            "email:admin[]exitsrus.example url:https://exitsrus.example \
+             proof:uri-rsa ciissversion:2",
+        );
+        assert!(info.is_ciiss());
+        assert_eq!(info.email().as_deref(), Some("admin@exitsrus.example"));
+        assert_eq!(info.url(), Some("https://exitsrus.example"));
+        assert_eq!(info.proof(), Some("uri-rsa"));
+        assert_eq!(info.get("ciissversion"), Some("2"));
+        assert!(info.matches("email", Some("@exitsrus")));
+        assert!(info.matches("proof", None));
+        assert!(!info.matches("hoster", None));
+    }
+
+    #[test]
+    fn parse_free_form() {
+        let info = ContactInfo::parse(
+            "nestor <nestor AT example DOT net> abuse:abuse[]example.net",
+        );
+        assert!(!info.is_ciiss());
+        assert_eq!(info.email(), None);
+    }
+}
@@ -1,18 +1,48 @@
 use anyhow::Result;
 use async_trait::async_trait;
+use serde::Serialize;
 use std::fmt;
 use structopt::StructOpt;
 
-use crate::commands::find;
+use crate::commands::err::Error;
+use crate::commands::expr::{FilterArg, FilterExpr};
+use crate::commands::group::{self, Group, GroupBy};
+use crate::commands::output::{Format, RelayInfo};
 use crate::commands::util;
+use crate::commands::weight::Totals;
 use crate::commands::RunnableOffline;
 
 #[derive(StructOpt)]
 pub struct CountCommand {
     #[structopt(short = "l", long = "list")]
     list: bool,
-    /// The filters of this command.
-    filters: Vec<find::FindFilter>,
+    /// Output format: text, json or ndjson.
+    #[structopt(short = "f", long = "format", default_value = "text")]
+    format: Format,
+    /// Also count the matching relays per group, with their fraction of the
+    /// consensus weight: version, flag, country, asn, port, /24 or policy.
+    #[structopt(short = "g", long = "group-by")]
+    group_by: Option<GroupBy>,
+    /// The filter expression of this command.
+    filters: Vec<FilterArg>,
+}
+
+/// The count of relays matching a filter or the whole expression, for the
+/// machine-readable formats.
+#[derive(Serialize)]
+struct CountEntry {
+    filter: String,
+    count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    relays: Option<Vec<RelayInfo>>,
+}
+
+#[derive(Serialize)]
+struct CountReport {
+    filters: Vec<CountEntry>,
+    all: CountEntry,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<Group>>,
 }
 
 impl fmt::Display for CountCommand {
@@ -24,21 +54,82 @@ impl fmt::Display for CountCommand {
 #[async_trait]
 impl RunnableOffline for CountCommand {
     fn run(&self, netdir: &tor_netdir::NetDir) -> Result<()> {
-        // We'll go filter by filter and then do a final count of all filters.
-        for filter in &self.filters {
-            let count =
-                netdir.relays().filter(|r| filter.match_relay(r)).count();
-            println!("[+] {} relays match: {:?}", count, filter);
+        match self.format {
+            Format::Text | Format::Json | Format::Ndjson => (),
+            format => {
+                return Err(
+                    Error::UnrecognizedFormat(format!("{format:?}")).into()
+                )
+            }
+        }
+        let expr = FilterExpr::parse(&self.filters)?;
+        if let Some(group_by) = self.group_by {
+            group_by.load()?;
+        }
+        let totals = Totals::new(netdir);
+        let mut entries = Vec::new();
+        // We'll go filter by filter and then do a final count of the whole
+        // expression.
+        if self.format.is_machine() && self.list {
+            RelayInfo::load();
+        }
+        for filter in expr.filters() {
+            let relays: Vec<_> = netdir
+                .relays()
+                .filter(|r| filter.match_relay(netdir, &totals, r))
+                .collect();
+            if self.format.is_machine() {
+                entries.push(CountEntry {
+                    filter: format!("{:?}", filter),
+                    count: relays.len(),
+                    relays: self.list.then(|| {
+                        relays
+                            .iter()
+                            .map(|r| RelayInfo::new(netdir, &totals, r))
+                            .collect()
+                    }),
+                });
+                continue;
+            }
+            println!("[+] {} relays match: {:?}", relays.len(), filter);
             if self.list {
-                let find = find::FindCommand::new(&[filter.clone()]);
-                let relays = find.filter(netdir);
-                util::describe_relays(&relays, true, 4);
+                util::describe_relays(netdir, &totals, &relays, true, 4);
             }
         }
 
-        // Count relays with matching all filters together.
-        let find = find::FindCommand::new(&self.filters);
-        println!("[+] {} relays matched all", find.count(netdir));
+        // Count relays matching the expression.
+        let matched = expr.filter(netdir, &totals);
+        let all = CountEntry {
+            filter: expr.to_string(),
+            count: matched.len(),
+            relays: None,
+        };
+        let groups =
+            self.group_by.map(|by| group::group(netdir, &matched, by));
+        match self.format {
+            Format::Json => {
+                let report = CountReport {
+                    filters: entries,
+                    all,
+                    groups,
+                };
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+            Format::Ndjson => {
+                for entry in entries.iter().chain([&all]) {
+                    println!("{}", serde_json::to_string(entry)?);
+                }
+                for group in groups.iter().flatten() {
+                    println!("{}", serde_json::to_string(group)?);
+                }
+            }
+            _ => {
+                println!("[+] {} relays matched: {}", all.count, expr);
+                if let Some(groups) = &groups {
+                    group::print_histogram(groups, 4);
+                }
+            }
+        }
 
         Ok(())
     }
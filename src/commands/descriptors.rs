@@ -0,0 +1,345 @@
+//! Relay server descriptors, loaded from local CollecTor files.
+//!
+//! arti doesn't fetch server descriptors (see
+//! <https://gitlab.torproject.org/tpo/core/arti/-/issues/874>), so they're
+//! read from a file concatenating descriptors, as in the `recent` CollecTor
+//! directory, or from an archive of
+//! <https://collector.torproject.org/archive/relay-descriptors/server-descriptors/>,
+//! either a directory or a tarball.
+//!
+//! The descriptors are joined with the relays of the network directory by
+//! their Rsa identity, keeping the most recently published one. Their
+//! signatures aren't checked. They're loaded once and only when needed.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use crate::commands::collector::Archive;
+use crate::commands::err::Error;
+use crate::commands::util;
+
+use tor_llcrypto::pk::rsa::RsaIdentity;
+use tor_netdir::Relay;
+
+static PATH: OnceLock<PathBuf> = OnceLock::new();
+static DESCRIPTORS: OnceLock<Descriptors> = OnceLock::new();
+
+/// The most recent descriptor of every relay, by Rsa identity.
+pub type Descriptors = HashMap<RsaIdentity, ServerDescriptor>;
+
+/// The fields of a server descriptor that aren't in the consensus or the
+/// microdescriptors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerDescriptor {
+    pub nickname: String,
+    pub rsa_id: RsaIdentity,
+    pub address: String,
+    pub or_port: u16,
+    pub dir_port: u16,
+    pub platform: Option<String>,
+    pub published: SystemTime,
+    pub uptime: Option<u64>,
+    /// Average, burst and observed bandwidths, in bytes per second.
+    pub bandwidth_avg: u64,
+    pub bandwidth_burst: u64,
+    pub bandwidth_observed: u64,
+    pub contact: Option<String>,
+    /// The whole exit policy, one rule per item, eg. `accept *:80`.
+    pub exit_policy: Vec<String>,
+}
+
+impl ServerDescriptor {
+    /// Return the operating system part of the platform, eg. `Linux` for
+    /// `Tor 0.4.8.9 on Linux`.
+    pub fn operating_system(&self) -> Option<&str> {
+        self.platform.as_ref()?.split_once(" on ").map(|(_, os)| os)
+    }
+}
+
+/// Parse a descriptor from its lines, without the objects.
+fn parse_descriptor(lines: &[&str]) -> Result<ServerDescriptor, Error> {
+    let invalid = |what: &str| {
+        Error::InvalidDescriptor(format!("{what} in: {}", lines[0]))
+    };
+    let mut router = lines[0].split_whitespace().skip(1);
+    let mut next = |what| router.next().ok_or_else(|| invalid(what));
+    let nickname = next("nickname")?.to_string();
+    let address = next("address")?.to_string();
+    let or_port = next("ORPort")?.parse().map_err(|_| invalid("ORPort"))?;
+    let _socks_port = next("SocksPort")?;
+    let dir_port = next("DirPort")?.parse().map_err(|_| invalid("DirPort"))?;
+
+    let mut rsa_id = None;
+    let mut published = None;
+    let mut bandwidth = None;
+    let mut desc = ServerDescriptor {
+        nickname,
+        rsa_id: RsaIdentity::from_bytes(&[0; 20]).expect("20 bytes"),
+        address,
+        or_port,
+        dir_port,
+        platform: None,
+        published: SystemTime::UNIX_EPOCH,
+        uptime: None,
+        bandwidth_avg: 0,
+        bandwidth_burst: 0,
+        bandwidth_observed: 0,
+        contact: None,
+        exit_policy: Vec::new(),
+    };
+    for &line in &lines[1..] {
+        // Older descriptors prefix some keywords with `opt`.
+        let line = line.strip_prefix("opt ").unwrap_or(line);
+        let (keyword, args) = line.split_once(' ').unwrap_or((line, ""));
+        match keyword {
+            "fingerprint" => {
+                rsa_id = Some(
+                    util::parse_rsa_id(&args.replace(' ', ""))
+                        .map_err(|_| invalid("fingerprint"))?,
+                )
+            }
+            "platform" => desc.platform = Some(args.to_string()),
+            "published" => {
+                published = Some(
+                    humantime::parse_rfc3339_weak(args)
+                        .map_err(|_| invalid("published"))?,
+                )
+            }
+            "uptime" => {
+                desc.uptime =
+                    Some(args.parse().map_err(|_| invalid("uptime"))?)
+            }
+            "bandwidth" => {
+                let values = args
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<Result<Vec<u64>, _>>()
+                    .map_err(|_| invalid("bandwidth"))?;
+                bandwidth = match values[..] {
+                    [avg, burst, observed] => Some((avg, burst, observed)),
+                    _ => return Err(invalid("bandwidth")),
+                };
+            }
+            "contact" => desc.contact = Some(args.to_string()),
+            "accept" | "reject" => desc.exit_policy.push(line.to_string()),
+            _ => (),
+        }
+    }
+    desc.rsa_id = rsa_id.ok_or_else(|| invalid("fingerprint"))?;
+    desc.published = published.ok_or_else(|| invalid("published"))?;
+    (
+        desc.bandwidth_avg,
+        desc.bandwidth_burst,
+        desc.bandwidth_observed,
+    ) = bandwidth.ok_or_else(|| invalid("bandwidth"))?;
+    Ok(desc)
+}
+
+/// Parse an uptime of a filter, either seconds or a duration, eg. `7days`.
+pub fn parse_uptime(s: &str) -> Result<u64, Error> {
+    s.parse()
+        .or_else(|_| humantime::parse_duration(s).map(|d| d.as_secs()))
+        .map_err(|_| Error::InvalidFilter(format!("Invalid uptime: {s}")))
+}
+
+/// Parse a bandwidth of a filter in bytes per second, optionally with a
+/// `KB`, `MB` or `GB` unit, eg. `10MB`.
+pub fn parse_bandwidth(s: &str) -> Result<u64, Error> {
+    let upper = s.to_uppercase();
+    let (number, unit) =
+        [("GB", 1_000_000_000), ("MB", 1_000_000), ("KB", 1_000)]
+            .into_iter()
+            .find_map(|(suffix, unit)| {
+                upper.strip_suffix(suffix).map(|number| (number, unit))
+            })
+            .unwrap_or((upper.as_str(), 1));
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| Error::InvalidFilter(format!("Invalid bandwidth: {s}")))
+}
+
+/// Parse the descriptors of a document. The malformed descriptors are
+/// skipped with a warning.
+pub fn parse_descriptors(text: &str) -> Vec<ServerDescriptor> {
+    let mut descriptors = Vec::new();
+    let mut add = |lines: &[&str]| match parse_descriptor(lines) {
+        Ok(desc) => descriptors.push(desc),
+        Err(err) => eprintln!("[-] Skipping descriptor: {err}"),
+    };
+    let mut lines: Vec<&str> = Vec::new();
+    let mut in_object = false;
+    for line in text.lines() {
+        // Skip the keys, certificates and signatures.
+        if line.starts_with("-----BEGIN ") {
+            in_object = true;
+        } else if line.starts_with("-----END ") {
+            in_object = false;
+        } else if !in_object && !line.starts_with('@') {
+            if line.starts_with("router ") && !lines.is_empty() {
+                add(&lines);
+                lines.clear();
+            }
+            if line.starts_with("router ") || !lines.is_empty() {
+                lines.push(line);
+            }
+        }
+    }
+    if !lines.is_empty() {
+        add(&lines);
+    }
+    descriptors
+}
+
+/// Load the descriptors from a file, a directory or a tarball, keeping the
+/// most recently published descriptor of every relay.
+pub fn load_descriptors(path: &Path) -> Result<Descriptors> {
+    let mut descriptors = Descriptors::new();
+    let mut add = |text: &str| {
+        for desc in parse_descriptors(text) {
+            if descriptors
+                .get(&desc.rsa_id)
+                .is_none_or(|other| other.published < desc.published)
+            {
+                descriptors.insert(desc.rsa_id, desc);
+            }
+        }
+    };
+    let is_tarball = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.contains(".tar"));
+    if path.is_file() && !is_tarball {
+        add(&read_to_string(path)?);
+    } else {
+        Archive::new(path).walk(|_, reader| {
+            let mut text = String::new();
+            reader.read_to_string(&mut text)?;
+            add(&text);
+            Ok(())
+        })?;
+    }
+    Ok(descriptors)
+}
+
+/// Set the path of the descriptors.
+pub fn configure(path: &Path) {
+    // Only the first configuration is kept.
+    let _ = PATH.set(path.to_path_buf());
+}
+
+/// Whether descriptors were given.
+pub fn is_configured() -> bool {
+    PATH.get().is_some()
+}
+
+/// Load the descriptors from the configured path, if not already loaded.
+pub fn load() -> Result<&'static Descriptors, Error> {
+    if let Some(descriptors) = DESCRIPTORS.get() {
+        return Ok(descriptors);
+    }
+    let path = PATH.get().ok_or_else(|| {
+        Error::MissingDatabase("server descriptors".to_string())
+    })?;
+    let loaded = load_descriptors(path)
+        .map_err(|e| Error::InvalidDatabase(format!("{e:#}")))?;
+    Ok(DESCRIPTORS.get_or_init(|| loaded))
+}
+
+/// Return the descriptors if they're loaded.
+pub fn get() -> Option<&'static Descriptors> {
+    DESCRIPTORS.get()
+}
+
+/// Return the most recent descriptor of the relay, if any.
+pub fn relay_descriptor(relay: &Relay) -> Option<&'static ServerDescriptor> {
+    get()?.get(relay.rsa_id())
+}
+
+/// Load the descriptors in `testdata/`, for tests.
+#[cfg(test)]
+pub fn configure_testdata() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    configure(&root.join("testdata/server-descriptors"));
+    load().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_testdata() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let path = root.join("testdata/server-descriptors");
+        let all = parse_descriptors(&read_to_string(&path).unwrap());
+        assert_eq!(all.len(), 6);
+
+        let descriptors = load_descriptors(&path).unwrap();
+        assert_eq!(descriptors.len(), 5);
+        let id =
+            util::parse_rsa_id("03CE4B4816FE36618D586B976F932775E2C19613")
+                .unwrap();
+        // The most recent of the two descriptors of ExitOne.
+        let exit = &descriptors[&id];
+        assert_eq!(exit.nickname, "ExitOne");
+        assert_eq!(exit.platform.as_deref(), Some("Tor 0.4.8.9 on Linux"));
+        assert_eq!(exit.operating_system(), Some("Linux"));
+        assert_eq!(exit.uptime, Some(864000));
+        assert_eq!(exit.bandwidth_observed, 52428800);
+        assert_eq!(exit.or_port, 9001);
+        assert_eq!(exit.exit_policy.len(), 8);
+        assert_eq!(exit.exit_policy[1], "accept *:20-23");
+        assert!(exit.contact.as_ref().unwrap().contains("ciissversion:2"));
+    }
+
+    #[test]
+    fn filter_values() {
+        assert_eq!(parse_uptime("3600").unwrap(), 3600);
+        assert_eq!(parse_uptime("2days").unwrap(), 172800);
+        assert!(parse_uptime("soon").is_err());
+        assert_eq!(parse_bandwidth("45000").unwrap(), 45000);
+        assert_eq!(parse_bandwidth("10MB").unwrap(), 10_000_000);
+        assert_eq!(parse_bandwidth("2kb").unwrap(), 2000);
+        assert!(parse_bandwidth("fast").is_err());
+        assert!(parse_bandwidth("100000000000GB").is_err());
+    }
+
+    #[test]
+    fn parse_opt_keywords() {
+        let text = "router Old 192.0.2.1 9001 0 0\n\
+        opt platform Tor 0.2.9.17 on Linux\n\
+        opt fingerprint 03CE 4B48 16FE 3661 8D58 6B97 6F93 2775 E2C1 9613\n\
+        published 2017-01-01 00:00:00\n\
+        bandwidth 1000 2000 1500\n";
+        let all = parse_descriptors(text);
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].platform.as_deref(), Some("Tor 0.2.9.17 on Linux"));
+        assert_eq!(
+            all[0].rsa_id,
+            util::parse_rsa_id("03CE4B4816FE36618D586B976F932775E2C19613")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let broken = "router Broken 192.0.2.1 9001 0 0\nbandwidth 1 2\n";
+        assert!(matches!(
+            parse_descriptor(&broken.lines().collect::<Vec<_>>()),
+            Err(Error::InvalidDescriptor(_))
+        ));
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let valid =
+            read_to_string(root.join("testdata/server-descriptors")).unwrap();
+        let all = parse_descriptors(&format!("{broken}{valid}"));
+        assert_eq!(all.len(), 6);
+        assert!(all.iter().all(|desc| desc.nickname != "Broken"));
+    }
+}
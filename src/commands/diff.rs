@@ -0,0 +1,345 @@
+//! Differences between two consensuses.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+use structopt::StructOpt;
+
+use crate::commands::err::Error;
+use crate::commands::expr::{FilterArg, FilterExpr};
+use crate::commands::output::{Columns, Format, RelayInfo};
+use crate::commands::weight::Totals;
+use crate::commands::RunnableOffline;
+use crate::commands::{collector, consensus, util};
+
+use tor_netdir::{NetDir, Relay};
+
+/// The relay attributes compared when no columns are given.
+static DEFAULT_FIELDS: &str =
+    "nickname,flags,version,orports,policy4,policy6,family,weight";
+
+/// Where to load a consensus from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// The network directory the command runs with, from arti or the global
+    /// options.
+    Now,
+    /// A consensus and a microdescriptors file, `<consensus>,<microdescs>`.
+    Files(PathBuf, PathBuf),
+    /// A CollecTor archive, at the given time or the most recent consensus,
+    /// `<archive>[@<time>]`.
+    Archive(PathBuf, Option<SystemTime>),
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "now" {
+            return Ok(Source::Now);
+        }
+        // The paths may contain the separators: an existing path is an
+        // archive, otherwise only the last separator splits the source.
+        if Path::new(s).exists() {
+            return Ok(Source::Archive(s.into(), None));
+        }
+        if let Some((archive, at)) = s.rsplit_once('@') {
+            if let Ok(at) = humantime::parse_rfc3339_weak(at) {
+                return Ok(Source::Archive(archive.into(), Some(at)));
+            }
+        }
+        if let Some((consensus, microdescs)) = s.rsplit_once(',') {
+            return Ok(Source::Files(consensus.into(), microdescs.into()));
+        }
+        if s.contains('@') {
+            return Err(Error::InvalidSource(s.to_string()));
+        }
+        Ok(Source::Archive(s.into(), None))
+    }
+}
+
+impl Source {
+    /// Load the network directory, or nothing if it's the current one.
+    fn load(&self) -> Result<Option<NetDir>> {
+        Ok(match self {
+            Source::Now => None,
+            Source::Files(consensus, microdescs) => {
+                Some(consensus::load_netdir(consensus, microdescs)?)
+            }
+            Source::Archive(archive, at) => {
+                Some(collector::load_netdir(archive, *at)?)
+            }
+        })
+    }
+}
+
+#[derive(StructOpt)]
+pub struct DiffCommand {
+    /// Output format: text or json.
+    #[structopt(short = "f", long = "format", default_value = "text")]
+    format: Format,
+    /// Comma separated relay attributes to compare, as the columns of
+    /// `find`.
+    #[structopt(short = "c", long = "columns")]
+    columns: Option<Columns>,
+    /// The old consensus: `now`, `<consensus>,<microdescs>` or
+    /// `<archive>[@<time>]`.
+    from: Source,
+    /// The new consensus, as the old one.
+    to: Source,
+    /// Only compare the relays matching this filter expression in either
+    /// consensus.
+    filters: Vec<FilterArg>,
+}
+
+/// A relay attribute that changed.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+struct Change {
+    field: &'static str,
+    from: String,
+    to: String,
+}
+
+/// A relay in both consensuses, with its changes.
+#[derive(Debug, Serialize)]
+struct Changed {
+    nickname: String,
+    rsa: String,
+    changes: Vec<Change>,
+}
+
+struct Diff<'a, 'b> {
+    added: Vec<Relay<'b>>,
+    removed: Vec<Relay<'a>>,
+    changed: Vec<Changed>,
+}
+
+#[derive(Serialize)]
+struct DiffReport<'a> {
+    added: Vec<RelayInfo>,
+    removed: Vec<RelayInfo>,
+    changed: &'a [Changed],
+}
+
+/// Compare the relays matching the expression in either network directory,
+/// each given with its totals.
+fn compare<'a, 'b>(
+    (from, from_totals): (&'a NetDir, &Totals),
+    (to, to_totals): (&'b NetDir, &Totals),
+    expr: &FilterExpr,
+    columns: &Columns,
+) -> Diff<'a, 'b> {
+    let mut diff = Diff {
+        added: Vec::new(),
+        removed: Vec::new(),
+        changed: Vec::new(),
+    };
+    for old in from.relays() {
+        let new = to.by_id(old.rsa_id());
+        if !expr.match_relay(from, from_totals, &old)
+            && !new
+                .as_ref()
+                .is_some_and(|new| expr.match_relay(to, to_totals, new))
+        {
+            continue;
+        }
+        let Some(new) = new else {
+            diff.removed.push(old);
+            continue;
+        };
+        let changes: Vec<_> = columns
+            .names()
+            .into_iter()
+            .zip(columns.values(from, from_totals, &old))
+            .zip(columns.values(to, to_totals, &new))
+            .filter(|((_, from), to)| from != to)
+            .map(|((field, from), to)| Change { field, from, to })
+            .collect();
+        if !changes.is_empty() {
+            diff.changed.push(Changed {
+                nickname: new.rs().nickname().to_string(),
+                rsa: new.rsa_id().to_string().replace('$', "").to_uppercase(),
+                changes,
+            });
+        }
+    }
+    diff.added = to
+        .relays()
+        .filter(|new| from.by_id(new.rsa_id()).is_none())
+        .filter(|new| expr.match_relay(to, to_totals, new))
+        .collect();
+    diff
+}
+
+#[async_trait]
+impl RunnableOffline for DiffCommand {
+    fn run(&self, netdir: &NetDir) -> Result<()> {
+        let expr = FilterExpr::parse(&self.filters)?;
+        let columns = match &self.columns {
+            Some(columns) => columns.clone(),
+            None => DEFAULT_FIELDS.parse()?,
+        };
+        columns.load()?;
+        let from = self.from.load()?;
+        let to = self.to.load()?;
+        let from = from.as_ref().unwrap_or(netdir);
+        let to = to.as_ref().unwrap_or(netdir);
+        let from_totals = Totals::new(from);
+        let to_totals = Totals::new(to);
+        let diff =
+            compare((from, &from_totals), (to, &to_totals), &expr, &columns);
+
+        match self.format {
+            Format::Text => {
+                println!("[+] {} relays added", diff.added.len());
+                if !diff.added.is_empty() {
+                    util::describe_relays(
+                        to,
+                        &to_totals,
+                        &diff.added,
+                        true,
+                        4,
+                    );
+                }
+                println!("[-] {} relays removed", diff.removed.len());
+                if !diff.removed.is_empty() {
+                    util::describe_relays(
+                        from,
+                        &from_totals,
+                        &diff.removed,
+                        true,
+                        4,
+                    );
+                }
+                println!("[~] {} relays changed", diff.changed.len());
+                for changed in &diff.changed {
+                    println!("    {} {}", changed.nickname, changed.rsa);
+                    for change in &changed.changes {
+                        println!(
+                            "      > {}: {} -> {}",
+                            change.field, change.from, change.to
+                        );
+                    }
+                }
+            }
+            Format::Json => {
+                RelayInfo::load();
+                let report = DiffReport {
+                    added: diff
+                        .added
+                        .iter()
+                        .map(|r| RelayInfo::new(to, &to_totals, r))
+                        .collect(),
+                    removed: diff
+                        .removed
+                        .iter()
+                        .map(|r| RelayInfo::new(from, &from_totals, r))
+                        .collect(),
+                    changed: &diff.changed,
+                };
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+            format => {
+                return Err(
+                    Error::UnrecognizedFormat(format!("{format:?}")).into()
+                )
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The CollecTor archive of the collector tests, but with ExitTwo
+    /// upgraded and reweighted in the 13:00 consensus.
+    fn archive() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata/diff")
+    }
+
+    #[test]
+    fn source_from_str() {
+        assert_eq!("now".parse::<Source>().unwrap(), Source::Now);
+        assert_eq!(
+            "consensus,microdescs".parse::<Source>().unwrap(),
+            Source::Files("consensus".into(), "microdescs".into())
+        );
+        assert_eq!(
+            "archive".parse::<Source>().unwrap(),
+            Source::Archive("archive".into(), None)
+        );
+        assert!(matches!(
+            "archive@2023-05-01 12:00:00".parse::<Source>().unwrap(),
+            Source::Archive(_, Some(_))
+        ));
+        assert!("archive@yesterday".parse::<Source>().is_err());
+
+        // Only the last separator splits the source.
+        assert_eq!(
+            "a,b/consensus,a@b/microdescs".parse::<Source>().unwrap(),
+            Source::Files("a,b/consensus".into(), "a@b/microdescs".into())
+        );
+        let source = "a@b/archive@2023-05-01 12:00:00".parse::<Source>();
+        let Source::Archive(archive, Some(_)) = source.unwrap() else {
+            panic!("not an archive at a time");
+        };
+        assert_eq!(archive, PathBuf::from("a@b/archive"));
+        // An existing path is an archive, whatever it contains.
+        let dir = std::env::temp_dir().join("margot-diff@source,test");
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(
+            dir.to_str().unwrap().parse::<Source>().unwrap(),
+            Source::Archive(dir.clone(), None)
+        );
+    }
+
+    #[test]
+    fn diff_archive() {
+        let from = format!("{}@2023-05-01 12:30:00", archive().display());
+        let from = from.parse::<Source>().unwrap().load().unwrap().unwrap();
+        let to = collector::load_netdir(&archive(), None).unwrap();
+        let columns = DEFAULT_FIELDS.parse().unwrap();
+        let from = (&from, &Totals::new(&from));
+        let to = (&to, &Totals::new(&to));
+
+        let diff = compare(from, to, &FilterExpr::All, &columns);
+        assert!(diff.added.is_empty());
+        let removed: Vec<_> =
+            diff.removed.iter().map(|r| r.rs().nickname()).collect();
+        assert_eq!(removed, ["BadOne"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].nickname, "ExitTwo");
+        assert_eq!(
+            diff.changed[0].changes,
+            [
+                Change {
+                    field: "version",
+                    from: "0.4.8.4".to_string(),
+                    to: "0.4.8.9".to_string(),
+                },
+                Change {
+                    field: "weight",
+                    from: "7000".to_string(),
+                    to: "7500".to_string(),
+                },
+            ]
+        );
+
+        // Swapped, the removed relay is added.
+        let diff = compare(to, from, &FilterExpr::All, &columns);
+        assert_eq!(diff.added.len(), 1);
+        assert!(diff.removed.is_empty());
+
+        // Scoped to the guards, nothing changed.
+        let args = ["fl:guard".parse().unwrap()];
+        let expr = FilterExpr::parse(&args).unwrap();
+        let diff = compare(from, to, &expr, &columns);
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+        assert!(diff.changed.is_empty());
+    }
+}
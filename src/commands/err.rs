@@ -9,18 +9,54 @@ use tor_netdoc::types::policy::PolicyError;
 pub enum Error {
     #[error("Invalid filter: {0}")]
     InvalidFilter(String),
+    #[error("Invalid port range: {0}")]
+    InvalidPortRange(String),
+    #[error("Invalid version: {0}")]
+    InvalidVersion(String),
+    #[error("Invalid database: {0}")]
+    InvalidDatabase(String),
+    #[error("Invalid server descriptor: {0}")]
+    InvalidDescriptor(String),
+    #[error("Invalid metric: {0}")]
+    InvalidMetric(String),
+    #[error("Invalid consensus source: {0}")]
+    InvalidSource(String),
+    #[error("Invalid filter expression: {0}")]
+    InvalidExpression(String),
     #[error("Undecodable fingerprint: {0}")]
     UndecodableFingerprint(String),
+    #[error("Unrecognized column: {0}")]
+    UnrecognizedColumn(String),
     #[error("Unrecognized filter: {0}")]
     UnrecognizedFilter(String),
+    #[error("Unrecognized field: {0}")]
+    UnrecognizedField(String),
+    #[error("Unrecognized metric: {0}")]
+    UnrecognizedMetric(String),
+    #[error("Unrecognized group: {0}")]
+    UnrecognizedGroup(String),
+    #[error("Unrecognized format: {0}")]
+    UnrecognizedFormat(String),
     #[error("Wrong fingerprint length: {0}")]
     WrongFingerprintLength(String),
     #[error("Policy error: {0}")]
     WrongPolicy(#[from] PolicyError),
+    #[error("Regex error: {0}")]
+    WrongRegex(#[from] regex::Error),
+    #[error("Database error: {0}")]
+    WrongDatabase(#[from] rusqlite::Error),
     #[error("IO error: {0}")]
     WrongIO(#[from] std::io::Error),
     #[error("Wrong parent: {0}")]
     WrongParent(String),
+    #[error("No {0} database given")]
+    MissingDatabase(String),
     #[error("No such relay")]
     NoSuchRelay,
+    #[error("Not enough microdescriptors, {0} missing")]
+    InsufficientDirectory(usize),
+    #[error("No consensus valid at the given time in: {0}")]
+    NoConsensus(String),
+    #[error("This command needs a connection to the Tor network")]
+    NetworkRequired,
 }
@@ -0,0 +1,380 @@
+//! Boolean expressions of filters.
+//!
+//! eg: `(fl:exit or fl:guard) and not v:0.4.7`
+//!
+//! The operators are `not`, `and` and `or`, from the highest to the lowest
+//! precedence, and parentheses group sub-expressions. Filters written one
+//! after another without an operator are ANDed together, so a plain list of
+//! filters works as it always did.
+//!
+//! An expression can be given as many command line arguments or quoted as
+//! a single one.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::commands::err::Error;
+use crate::commands::find::FindFilter;
+use crate::commands::weight::Totals;
+
+#[derive(Debug, Clone)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Filter(FindFilter),
+}
+
+/// Return the operator token for a word, if it is one.
+fn operator(word: &str) -> Option<Token> {
+    match word.to_lowercase().as_str() {
+        "and" => Some(Token::And),
+        "or" => Some(Token::Or),
+        "not" => Some(Token::Not),
+        _ => None,
+    }
+}
+
+/// Return the first word of `s`, up to a whitespace or a parenthesis.
+fn first_word(s: &str) -> &str {
+    let end = s
+        .find(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .unwrap_or(s.len());
+    &s[..end]
+}
+
+/// Whether the word starts like a filter, ie. `<key>:`.
+fn is_filter(word: &str) -> bool {
+    match word.split_once(':') {
+        Some((key, _)) => {
+            key.starts_with(|c: char| c.is_ascii_alphabetic())
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-<>=!%".contains(c))
+        }
+        None => false,
+    }
+}
+
+/// Return the length of the filter at the start of `s`.
+///
+/// Some filter values contain whitespaces, eg. `pp:accept 80,443`, so a
+/// filter only ends before an operator, a parenthesis or another filter.
+/// Parentheses which are balanced belong to the filter, eg. in a regular
+/// expression.
+fn filter_len(s: &str) -> usize {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return i,
+            ')' => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => {
+                let next = s[i..].trim_start();
+                let word = first_word(next);
+                if word.is_empty()
+                    || operator(word).is_some()
+                    || is_filter(word)
+                {
+                    return i;
+                }
+            }
+            _ => (),
+        }
+    }
+    s.len()
+}
+
+/// A command line argument holding part of a filter expression.
+#[derive(Debug, Clone)]
+pub struct FilterArg(Vec<Token>);
+
+impl FilterArg {
+    /// Return the filters in this argument, regardless of the operators.
+    pub fn filters(&self) -> impl Iterator<Item = &FindFilter> {
+        self.0.iter().filter_map(|token| match token {
+            Token::Filter(f) => Some(f),
+            _ => None,
+        })
+    }
+}
+
+impl From<FindFilter> for FilterArg {
+    fn from(filter: FindFilter) -> Self {
+        FilterArg(vec![Token::Filter(filter)])
+    }
+}
+
+impl FromStr for FilterArg {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = Vec::new();
+        let mut rest = s.trim_start();
+        while !rest.is_empty() {
+            let len = if let Some(r) = rest.strip_prefix('(') {
+                tokens.push(Token::Open);
+                rest.len() - r.len()
+            } else if let Some(r) = rest.strip_prefix(')') {
+                tokens.push(Token::Close);
+                rest.len() - r.len()
+            } else if let Some(op) = operator(first_word(rest)) {
+                tokens.push(op);
+                first_word(rest).len()
+            } else {
+                let len = filter_len(rest);
+                tokens.push(Token::Filter(rest[..len].trim_end().parse()?));
+                len
+            };
+            rest = rest[len..].trim_start();
+        }
+        Ok(FilterArg(tokens))
+    }
+}
+
+/// A boolean expression of [FindFilter]s.
+#[derive(Debug, Clone)]
+pub enum FilterExpr {
+    /// The empty expression, it matches every relay.
+    All,
+    Filter(FindFilter),
+    Not(Box<FilterExpr>),
+    And(Vec<FilterExpr>),
+    Or(Vec<FilterExpr>),
+}
+
+/// A recursive descent parser of a list of tokens.
+struct Parser<'a> {
+    tokens: Vec<&'a Token>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn error(&self, reason: &str) -> Error {
+        Error::InvalidExpression(format!("{} at token {}", reason, self.pos))
+    }
+
+    /// or := and ("or" and)*
+    fn or(&mut self) -> Result<FilterExpr, Error> {
+        let mut exprs = vec![self.and()?];
+        while let Some(Token::Or) = self.peek() {
+            self.next();
+            exprs.push(self.and()?);
+        }
+        Ok(flatten(exprs, FilterExpr::Or))
+    }
+
+    /// and := unary (["and"] unary)*
+    fn and(&mut self) -> Result<FilterExpr, Error> {
+        let mut exprs = vec![self.unary()?];
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next();
+                }
+                Some(Token::Open | Token::Not | Token::Filter(_)) => (),
+                _ => break,
+            }
+            exprs.push(self.unary()?);
+        }
+        Ok(flatten(exprs, FilterExpr::And))
+    }
+
+    /// unary := "not" unary | "(" or ")" | filter
+    fn unary(&mut self) -> Result<FilterExpr, Error> {
+        match self.next() {
+            Some(Token::Not) => Ok(FilterExpr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(self.error("Missing closing parenthesis")),
+                }
+            }
+            Some(Token::Filter(f)) => Ok(FilterExpr::Filter(f.clone())),
+            Some(_) => Err(self.error("Unexpected operator")),
+            None => Err(self.error("Unexpected end of expression")),
+        }
+    }
+}
+
+fn flatten(
+    mut exprs: Vec<FilterExpr>,
+    op: fn(Vec<FilterExpr>) -> FilterExpr,
+) -> FilterExpr {
+    if exprs.len() == 1 {
+        return exprs.remove(0);
+    }
+    op(exprs)
+}
+
+impl FilterExpr {
+    /// Parse the expression given as command line arguments.
+    pub fn parse(args: &[FilterArg]) -> Result<Self, Error> {
+        let mut parser = Parser {
+            tokens: args.iter().flat_map(|arg| arg.0.iter()).collect(),
+            pos: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Ok(FilterExpr::All);
+        }
+        let expr = parser.or()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("Unexpected token"));
+        }
+        for filter in expr.filters() {
+            filter.load()?;
+        }
+        Ok(expr)
+    }
+
+    pub fn match_relay(
+        &self,
+        netdir: &tor_netdir::NetDir,
+        totals: &Totals,
+        relay: &tor_netdir::Relay,
+    ) -> bool {
+        match self {
+            FilterExpr::All => true,
+            FilterExpr::Filter(f) => f.match_relay(netdir, totals, relay),
+            FilterExpr::Not(e) => !e.match_relay(netdir, totals, relay),
+            FilterExpr::And(es) => {
+                es.iter().all(|e| e.match_relay(netdir, totals, relay))
+            }
+            FilterExpr::Or(es) => {
+                es.iter().any(|e| e.match_relay(netdir, totals, relay))
+            }
+        }
+    }
+
+    /// Return the filters in the expression, regardless of the operators.
+    pub fn filters(&self) -> Vec<&FindFilter> {
+        match self {
+            FilterExpr::All => Vec::new(),
+            FilterExpr::Filter(f) => vec![f],
+            FilterExpr::Not(e) => e.filters(),
+            FilterExpr::And(es) | FilterExpr::Or(es) => {
+                es.iter().flat_map(|e| e.filters()).collect()
+            }
+        }
+    }
+
+    pub fn filter<'a>(
+        &self,
+        netdir: &'a tor_netdir::NetDir,
+        totals: &Totals,
+    ) -> Vec<tor_netdir::Relay<'a>> {
+        netdir
+            .relays()
+            .filter(|r| self.match_relay(netdir, totals, r))
+            .collect()
+    }
+}
+
+impl fmt::Display for FilterExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |es: &[FilterExpr], op: &str| {
+            es.iter()
+                .map(|e| format!("({})", e))
+                .collect::<Vec<_>>()
+                .join(op)
+        };
+        match self {
+            FilterExpr::All => write!(f, "all"),
+            FilterExpr::Filter(filter) => write!(f, "{:?}", filter),
+            FilterExpr::Not(e) => write!(f, "not ({})", e),
+            FilterExpr::And(es) => write!(f, "{}", join(es, " and ")),
+            FilterExpr::Or(es) => write!(f, "{}", join(es, " or ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::consensus;
+
+    fn parse(args: &[&str]) -> Result<FilterExpr, Error> {
+        let args: Vec<FilterArg> =
+            args.iter().map(|a| a.parse()).collect::<Result<_, _>>()?;
+        FilterExpr::parse(&args)
+    }
+
+    #[test]
+    fn tokenize_quoted_expression() {
+        let arg: FilterArg =
+            "(fl:exit or fl:guard) and not v:0.4.7".parse().unwrap();
+        assert!(matches!(
+            arg.0.as_slice(),
+            [
+                Token::Open,
+                Token::Filter(_),
+                Token::Or,
+                Token::Filter(_),
+                Token::Close,
+                Token::And,
+                Token::Not,
+                Token::Filter(_),
+            ]
+        ));
+    }
+
+    #[test]
+    fn tokenize_filter_with_whitespaces() {
+        let arg: FilterArg = "pp:accept 80,443 or (n:moria1)".parse().unwrap();
+        assert_eq!(arg.0.len(), 5);
+        assert_eq!(arg.filters().count(), 2);
+    }
+
+    #[test]
+    fn parse_precedence() {
+        let expr = parse(&["fl:exit", "fl:guard", "or", "not", "p:443"]);
+        match expr.unwrap() {
+            FilterExpr::Or(es) => {
+                assert!(matches!(es[0], FilterExpr::And(_)));
+                assert!(matches!(es[1], FilterExpr::Not(_)));
+            }
+            e => panic!("Unexpected expression: {}", e),
+        }
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert!(matches!(parse(&[]).unwrap(), FilterExpr::All));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse(&["(fl:exit", "or", "fl:guard"]).is_err());
+        assert!(parse(&["fl:exit", "or"]).is_err());
+        assert!(parse(&["fl:exit)"]).is_err());
+        assert!(parse(&["and", "fl:exit"]).is_err());
+    }
+
+    #[test]
+    fn match_expression() {
+        let netdir = consensus::test_netdir();
+        let expr = parse(&["(fl:exit or fl:guard) and not v:0.4.8"]);
+        let nicknames: Vec<_> = expr
+            .unwrap()
+            .filter(&netdir, &Totals::new(&netdir))
+            .iter()
+            .map(|r| r.rs().nickname().to_string())
+            .collect();
+        assert_eq!(nicknames.len(), 2);
+        assert!(nicknames.contains(&"BadOne".to_string()));
+        assert!(nicknames.contains(&"GuardBeta".to_string()));
+    }
+}
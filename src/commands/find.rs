@@ -1,19 +1,32 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use ipnetwork::IpNetwork;
+use regex::Regex;
 use std::fmt;
 use std::path::Path;
 use std::str::FromStr;
+use std::time::SystemTime;
 use structopt::StructOpt;
 
+use crate::commands::asn;
+use crate::commands::contact;
+use crate::commands::descriptors;
 use crate::commands::err::Error;
+use crate::commands::expr::{FilterArg, FilterExpr};
+use crate::commands::geoip;
+use crate::commands::history;
+use crate::commands::output::{self, Columns, Format};
 use crate::commands::util;
+use crate::commands::version::TorVersion;
+use crate::commands::weight::{self, Totals, WeightKind};
 use crate::commands::RunnableOffline;
 
+use tor_llcrypto::pk::rsa::RsaIdentity;
 use tor_netdoc::doc::netstatus;
 use tor_netdoc::types::policy::PortPolicy;
 
 #[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum Filter {
     /// Address or Network
     Address(IpNetwork),
@@ -23,12 +36,61 @@ pub enum Filter {
     Flags(netstatus::RelayFlags),
     /// Relay nickname
     Nickname(String),
+    /// Relay nickname, case insensitive. It's kept in lowercase.
+    NicknameInsensitive(String),
+    /// Relay nickname regular expression, also used for globs
+    NicknameRegex(Regex),
     /// ORPort
     Port(u16),
-    /// Relay version
-    Version(String),
+    /// Relay version, matching the versions starting with it
+    Version(TorVersion),
+    /// Relay version, compared to the given one
+    VersionCmp(util::Comparison, TorVersion),
     /// Port policy
     PortPolicyFilter(PortPolicy),
+    /// Exit port range allowed by the IPv4 or IPv6 (when true) port policy
+    PolicyAllows(bool, u16, u16),
+    /// Exit port range rejected by the IPv4 or IPv6 (when true) port policy
+    PolicyRejects(bool, u16, u16),
+    /// Consensus weight
+    Weight(util::Comparison, u32),
+    /// Whether the consensus weight was measured by the bandwidth authorities
+    Measured(bool),
+    /// Percentage of the total weight carried by the relay
+    WeightFraction(util::Comparison, WeightKind, f64),
+    /// The heaviest relays which together carry this percentage of the total
+    /// weight
+    TopWeight(WeightKind, f64),
+    /// Relays listing this relay in their family
+    Family(RsaIdentity),
+    /// Number of relays in the family
+    FamilySize(util::Comparison, usize),
+    /// Relays listing this relay in their family, which lists them back
+    MutualFamily(RsaIdentity),
+    /// Country code of any of the relay addresses, from the GeoIP database
+    Country(String),
+    /// Autonomous System number of any of the relay addresses
+    Asn(u32),
+    /// Case insensitive part of the name of the relay Autonomous Systems
+    AsName(String),
+    /// When the relay was first seen, from the history database
+    FirstSeen(util::Comparison, SystemTime),
+    /// When the relay was last seen, from the history database
+    LastSeen(util::Comparison, SystemTime),
+    /// Case insensitive part of the platform, from the server descriptors
+    Platform(String),
+    /// Uptime in seconds, from the server descriptors
+    Uptime(util::Comparison, u64),
+    /// Observed bandwidth in bytes per second, from the server descriptors
+    ObservedBandwidth(util::Comparison, u64),
+    /// Average bandwidth in bytes per second, from the server descriptors
+    BandwidthRate(util::Comparison, u64),
+    /// Case insensitive part of the contact, from the server descriptors
+    Contact(String),
+    /// Contact regular expression
+    ContactRegex(Regex),
+    /// Key of a CIISS contact field, and case insensitive part of its value
+    Ciiss(String, Option<String>),
     FpsFileFilter(Vec<util::RelayFingerprint>),
 }
 
@@ -46,73 +108,184 @@ impl FindFilter {
         }
     }
 
-    pub fn match_relay(&self, relay: &tor_netdir::Relay) -> bool {
+    /// Load the databases the filter needs, before matching any relay.
+    pub fn load(&self) -> Result<(), Error> {
+        match self.filter {
+            Filter::Country(_) => {
+                geoip::load()?;
+            }
+            Filter::Asn(_) | Filter::AsName(_) => {
+                asn::load()?;
+            }
+            Filter::FirstSeen(..) | Filter::LastSeen(..) => {
+                history::load()?;
+            }
+            Filter::Platform(_)
+            | Filter::Uptime(..)
+            | Filter::ObservedBandwidth(..)
+            | Filter::BandwidthRate(..)
+            | Filter::Contact(_)
+            | Filter::ContactRegex(_)
+            | Filter::Ciiss(..) => {
+                descriptors::load()?;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    pub fn match_relay(
+        &self,
+        netdir: &tor_netdir::NetDir,
+        totals: &Totals,
+        relay: &tor_netdir::Relay,
+    ) -> bool {
         let mut ret = match &self.filter {
             Filter::Address(a) => {
                 relay.rs().orport_addrs().any(|addr| a.contains(addr.ip()))
             }
             Filter::Nickname(n) => relay.rs().nickname().contains(n),
+            Filter::NicknameInsensitive(n) => {
+                relay.rs().nickname().to_lowercase().contains(n)
+            }
+            Filter::NicknameRegex(re) => re.is_match(relay.rs().nickname()),
             Filter::Fingerprint(fp) => fp.match_relay(relay),
             Filter::Flags(f) => relay.rs().flags().contains(*f),
             Filter::Port(p) => {
                 relay.rs().orport_addrs().any(|addr| addr.port() == *p)
             }
-            Filter::Version(v) => relay
-                .rs()
-                .version()
-                .as_ref()
-                .expect("version error")
-                .to_string()
-                .contains(v),
+            Filter::Version(v) => util::get_version(relay)
+                .parse::<TorVersion>()
+                .is_ok_and(|version| version.starts_with(v)),
+            Filter::VersionCmp(cmp, v) => util::get_version(relay)
+                .parse::<TorVersion>()
+                .is_ok_and(|version| cmp.compare(&version, v)),
             Filter::PortPolicyFilter(pp) => &**relay.md().ipv4_policy() == pp,
             // ^ this is `&Arc<PortPolicy>`, 1st dereference `Arc`,
             // then `&`, then add `&` to match `&PortPolicy`
+            Filter::PolicyAllows(ipv6, lo, hi) => {
+                let policy = md_policy(relay, *ipv6);
+                (*lo..=*hi).all(|port| policy.allows_port(port))
+            }
+            Filter::PolicyRejects(ipv6, lo, hi) => {
+                let policy = md_policy(relay, *ipv6);
+                !(*lo..=*hi).any(|port| policy.allows_port(port))
+            }
             // The following will try to find the relay in the list of parsed
             // fingerprints from a file.
             Filter::FpsFileFilter(ff) => {
                 ff.iter().any(|fp| fp.match_relay(relay))
             }
+            Filter::Weight(cmp, w) => {
+                cmp.compare(&util::get_weight(relay).0, w)
+            }
+            Filter::Measured(m) => util::get_weight(relay).1 == *m,
+            Filter::WeightFraction(cmp, kind, percent) => {
+                let total = totals.total(*kind);
+                let weight = weight::relay_weight(netdir, relay, *kind);
+                total > 0
+                    && cmp.compare(
+                        &(weight as f64 * 100.0 / total as f64),
+                        percent,
+                    )
+            }
+            Filter::TopWeight(kind, percent) => {
+                weight::relay_weight(netdir, relay, *kind)
+                    >= totals.top_threshold(*kind, *percent)
+            }
+            Filter::Family(id) => {
+                relay.md().family().members().any(|m| m == id)
+            }
+            Filter::FamilySize(cmp, size) => {
+                cmp.compare(&relay.md().family().members().count(), size)
+            }
+            Filter::MutualFamily(id) => {
+                relay.md().family().members().any(|m| m == id)
+                    && netdir.by_id(id).is_some_and(|other| {
+                        other
+                            .md()
+                            .family()
+                            .members()
+                            .any(|m| m == relay.rsa_id())
+                    })
+            }
+            Filter::Country(cc) => {
+                geoip::relay_countries(relay).contains(&cc.as_str())
+            }
+            Filter::Asn(number) => {
+                asn::relay_ases(relay).iter().any(|a| a.number == *number)
+            }
+            Filter::AsName(name) => asn::relay_ases(relay)
+                .iter()
+                .any(|a| a.name.to_lowercase().contains(name)),
+            Filter::FirstSeen(cmp, time) => history::relay_seen(relay)
+                .is_some_and(|seen| cmp.compare(&seen.first, time)),
+            Filter::LastSeen(cmp, time) => history::relay_seen(relay)
+                .is_some_and(|seen| cmp.compare(&seen.last, time)),
+            Filter::Platform(platform) => descriptors::relay_descriptor(relay)
+                .is_some_and(|desc| {
+                    desc.platform
+                        .as_ref()
+                        .is_some_and(|p| p.to_lowercase().contains(platform))
+                }),
+            Filter::Uptime(cmp, uptime) => {
+                descriptors::relay_descriptor(relay)
+                    .and_then(|desc| desc.uptime)
+                    .is_some_and(|u| cmp.compare(&u, uptime))
+            }
+            Filter::ObservedBandwidth(cmp, bw) => {
+                descriptors::relay_descriptor(relay).is_some_and(|desc| {
+                    cmp.compare(&desc.bandwidth_observed, bw)
+                })
+            }
+            Filter::BandwidthRate(cmp, bw) => {
+                descriptors::relay_descriptor(relay)
+                    .is_some_and(|desc| cmp.compare(&desc.bandwidth_avg, bw))
+            }
+            Filter::Contact(s) => contact::relay_contact(relay)
+                .is_some_and(|c| c.to_lowercase().contains(s)),
+            Filter::ContactRegex(re) => {
+                contact::relay_contact(relay).is_some_and(|c| re.is_match(c))
+            }
+            Filter::Ciiss(key, value) => contact::relay_contact_info(relay)
+                .matches(key, value.as_deref()),
         };
         ret ^= self.exclude;
         ret
     }
 }
 
+/// Return the IPv4 or IPv6 policy of the relay's microdescriptor, rather
+/// than the relay's one, which rejects everything when it's a bad exit.
+pub fn md_policy<'a>(
+    relay: &'a tor_netdir::Relay,
+    ipv6: bool,
+) -> &'a PortPolicy {
+    if ipv6 {
+        relay.md().ipv6_policy()
+    } else {
+        relay.md().ipv4_policy()
+    }
+}
+
 #[derive(StructOpt)]
 pub struct FindCommand {
     #[structopt(short = "l", long = "oneline")]
     oneline: bool,
-    /// The filters of the find command.
-    filters: Vec<FindFilter>,
-}
-
-impl FindCommand {
-    pub fn new(filters: &[FindFilter]) -> Self {
-        FindCommand {
-            oneline: false,
-            filters: filters.to_vec(),
-        }
-    }
-
-    pub fn match_relay(&self, relay: &tor_netdir::Relay) -> bool {
-        for filter in &self.filters {
-            if !filter.match_relay(relay) {
-                return false;
-            }
-        }
-        true
-    }
-
-    pub fn filter<'a>(
-        &self,
-        netdir: &'a tor_netdir::NetDir,
-    ) -> Vec<tor_netdir::Relay<'a>> {
-        netdir.relays().filter(|r| self.match_relay(r)).collect()
-    }
-
-    pub fn count(&self, netdir: &tor_netdir::NetDir) -> usize {
-        netdir.relays().filter(|r| self.match_relay(r)).count()
-    }
+    /// Output format: text, oneline, json, ndjson, csv or tsv.
+    #[structopt(short = "f", long = "format", default_value = "text")]
+    format: Format,
+    /// Comma separated columns for the oneline, csv and tsv formats, eg.
+    /// `nickname,rsa,flags,weight,policy4,family`.
+    #[structopt(short = "c", long = "columns")]
+    columns: Option<Columns>,
+    /// Print the total share of the network of the relays instead of the
+    /// relays: their consensus weight fraction and their probabilities to be
+    /// picked as guard, middle or exit.
+    #[structopt(short = "s", long = "summary")]
+    summary: bool,
+    /// The filter expression of the find command.
+    filters: Vec<FilterArg>,
 }
 
 impl fmt::Display for FindCommand {
@@ -125,17 +298,31 @@ impl FromStr for FindFilter {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let exclude = s.contains("-:");
-        if let Some(kv) = s.to_string().replace("-:", "").split_once(':') {
-            let filter = match kv.0 {
+        if let Some(kv) = s.split_once(':') {
+            // The exclusion is written right before the first `:`, the value
+            // may contain `-:` too.
+            let (key, exclude) = match kv.0.strip_suffix('-') {
+                Some(key) => (key, true),
+                None => (kv.0, false),
+            };
+            let filter = match key {
                 "a" | "addr" => Filter::Address(kv.1.parse().unwrap()),
                 "fl" | "flag" => Filter::Flags(util::parse_routerflag(kv.1)),
                 "f" | "fp" => Filter::Fingerprint(
                     kv.1.parse::<util::RelayFingerprint>()?,
                 ),
                 "n" | "nick" => Filter::Nickname(String::from(kv.1)),
+                "ni" | "nickinsensitive" => {
+                    Filter::NicknameInsensitive(kv.1.to_lowercase())
+                }
+                // The regular expression is compiled once here rather than
+                // for every relay.
+                "nr" | "nickregex" => Filter::NicknameRegex(Regex::new(kv.1)?),
+                "ng" | "nickglob" => {
+                    Filter::NicknameRegex(util::glob2regex(kv.1)?)
+                }
                 "p" | "port" => Filter::Port(kv.1.parse().unwrap()),
-                "v" | "version" => Filter::Version(String::from(kv.1)),
+                "v" | "version" => Filter::Version(kv.1.parse()?),
                 // Because a [PortPolicy] already contains `accept` or
                 // `reject`, there is no need to use the `exclude` argument
                 // (`-`). If used, it will still negate the policy, ie.
@@ -149,10 +336,107 @@ impl FromStr for FindFilter {
                 "pf" | "portpolicyfile" => Filter::PortPolicyFilter(
                     util::portpolicyfile2portpolicy(Path::new(kv.1))?,
                 ),
+                // A port or a port range, eg. `allows:25` or `allows:80-443`
+                "allows" | "allows4" => {
+                    let (lo, hi) = util::parse_port_range(kv.1)?;
+                    Filter::PolicyAllows(false, lo, hi)
+                }
+                "allows6" => {
+                    let (lo, hi) = util::parse_port_range(kv.1)?;
+                    Filter::PolicyAllows(true, lo, hi)
+                }
+                "rejects" | "rejects4" => {
+                    let (lo, hi) = util::parse_port_range(kv.1)?;
+                    Filter::PolicyRejects(false, lo, hi)
+                }
+                "rejects6" => {
+                    let (lo, hi) = util::parse_port_range(kv.1)?;
+                    Filter::PolicyRejects(true, lo, hi)
+                }
                 "ff" | "fingerprintfile" => {
                     Filter::FpsFileFilter(util::fpfile2fps(Path::new(kv.1))?)
                 }
-                _ => return Err(Error::UnrecognizedFilter(kv.0.to_string())),
+                "fam" | "family" => Filter::Family(util::parse_rsa_id(kv.1)?),
+                "mutualfam" | "mutualfamily" => {
+                    Filter::MutualFamily(util::parse_rsa_id(kv.1)?)
+                }
+                "cc" | "country" => Filter::Country(kv.1.to_uppercase()),
+                "as" | "asn" => Filter::Asn(asn::parse_asn(kv.1)?),
+                "asname" => Filter::AsName(kv.1.to_lowercase()),
+                "platform" => Filter::Platform(kv.1.to_lowercase()),
+                "contact" => Filter::Contact(kv.1.to_lowercase()),
+                "contactr" | "contactregex" => {
+                    Filter::ContactRegex(Regex::new(kv.1)?)
+                }
+                // A CIISS field, eg. `ciiss:proof` or
+                // `ciiss:email=@example.org`.
+                "ciiss" => {
+                    let value = kv.1.to_lowercase();
+                    match value.split_once('=') {
+                        Some((key, value)) => Filter::Ciiss(
+                            key.to_string(),
+                            Some(value.to_string()),
+                        ),
+                        None => Filter::Ciiss(value, None),
+                    }
+                }
+                "measured" => Filter::Measured(
+                    kv.1.parse()
+                        .map_err(|_| Error::InvalidFilter(s.to_string()))?,
+                ),
+                // The heaviest relays carrying a percentage of the total
+                // weight, eg. `top:1` or `top:guard:1%`.
+                "top" => {
+                    let (kind, percent) = weight::parse_percent(kv.1)?;
+                    Filter::TopWeight(kind, percent)
+                }
+                key => match util::Comparison::split_key(key) {
+                    Some(("v" | "version", cmp)) => {
+                        Filter::VersionCmp(cmp, kv.1.parse()?)
+                    }
+                    Some(("w" | "weight", cmp)) => Filter::Weight(
+                        cmp,
+                        kv.1.parse().map_err(|_| {
+                            Error::InvalidFilter(s.to_string())
+                        })?,
+                    ),
+                    Some(("firstseen", cmp)) => {
+                        Filter::FirstSeen(cmp, history::parse_time(kv.1)?)
+                    }
+                    Some(("lastseen", cmp)) => {
+                        Filter::LastSeen(cmp, history::parse_time(kv.1)?)
+                    }
+                    Some(("uptime", cmp)) => {
+                        Filter::Uptime(cmp, descriptors::parse_uptime(kv.1)?)
+                    }
+                    Some(("bw" | "bandwidth", cmp)) => {
+                        Filter::ObservedBandwidth(
+                            cmp,
+                            descriptors::parse_bandwidth(kv.1)?,
+                        )
+                    }
+                    Some(("bwrate", cmp)) => Filter::BandwidthRate(
+                        cmp,
+                        descriptors::parse_bandwidth(kv.1)?,
+                    ),
+                    Some(("famsize" | "familysize", cmp)) => {
+                        Filter::FamilySize(
+                            cmp,
+                            kv.1.parse().map_err(|_| {
+                                Error::InvalidFilter(s.to_string())
+                            })?,
+                        )
+                    }
+                    // The percentage of the total weight, eg. `wf>:1` or
+                    // `wf>:exit:0.5%`.
+                    Some(("wf" | "weightfraction", cmp)) => {
+                        let (kind, percent) = weight::parse_percent(kv.1)?;
+                        Filter::WeightFraction(cmp, kind, percent)
+                    }
+                    _ => {
+                        return Err(Error::UnrecognizedFilter(key.to_string()))
+                    }
+                },
             };
             return Ok(FindFilter::new(exclude, filter));
         }
@@ -163,20 +447,236 @@ impl FromStr for FindFilter {
 #[async_trait]
 impl RunnableOffline for FindCommand {
     fn run(&self, netdir: &tor_netdir::NetDir) -> Result<()> {
-        let relays = self.filter(netdir);
+        let totals = Totals::new(netdir);
+        let relays = FilterExpr::parse(&self.filters)?.filter(netdir, &totals);
+        let columns = self.columns.clone().unwrap_or_default();
+        columns.load()?;
+        let format = if self.oneline {
+            Format::Oneline
+        } else {
+            self.format
+        };
 
-        if relays.is_empty() {
+        if self.summary {
+            return output::print_summary(netdir, &totals, &relays, format);
+        }
+        if relays.is_empty() && !format.is_machine() {
             println!("[-] No relays found");
             return Ok(());
         }
-        util::describe_relays(&relays, self.oneline, 0);
-        Ok(())
+        output::print_relays(netdir, &totals, &relays, format, &columns, 0)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::commands::consensus;
+
+    fn nicknames(filter: &str) -> Vec<String> {
+        let filter = FindFilter::from_str(filter).unwrap();
+        let netdir = consensus::test_netdir();
+        let totals = Totals::new(&netdir);
+        let mut nicknames: Vec<_> = netdir
+            .relays()
+            .filter(|r| filter.match_relay(&netdir, &totals, r))
+            .map(|r| r.rs().nickname().to_string())
+            .collect();
+        nicknames.sort();
+        nicknames
+    }
+
+    #[test]
+    fn nickname_filters() {
+        assert!(nicknames("n:exit").is_empty());
+        assert_eq!(nicknames("ni:exit"), vec!["ExitOne", "ExitTwo"]);
+        assert_eq!(
+            nicknames("nr:^Guard(Alpha|Beta)$"),
+            vec!["GuardAlpha", "GuardBeta"]
+        );
+        assert_eq!(nicknames("ng:*Relay"), vec!["MiddleOnlyRelay"]);
+        assert_eq!(
+            nicknames("ng:[!EG]*"),
+            vec!["BadOne", "MiddleOnlyRelay", "nestor00patof"]
+        );
+    }
+
+    #[test]
+    fn version_filters() {
+        assert_eq!(nicknames("v:0.4.1"), vec!["GuardBeta"]);
+        assert_eq!(nicknames("v:0.4.8.1"), Vec::<String>::new());
+        assert_eq!(
+            nicknames("v<:0.4.8"),
+            vec!["BadOne", "GuardBeta", "MiddleOnlyRelay", "nestor00patof"]
+        );
+        assert_eq!(nicknames("v>=:0.4.8.9"), vec!["ExitOne", "GuardAlpha"]);
+        assert_eq!(nicknames("v=:0.4.8.4"), vec!["ExitTwo"]);
+        assert!(FindFilter::from_str("v<:0.4.x").is_err());
+    }
+
+    #[test]
+    fn weight_filters() {
+        assert_eq!(nicknames("w>:10000"), vec!["GuardAlpha", "GuardBeta"]);
+        assert_eq!(nicknames("w<:50"), vec!["nestor00patof"]);
+        assert_eq!(nicknames("measured:false"), vec!["BadOne"]);
+        assert_eq!(nicknames("wf>:30"), vec!["GuardAlpha"]);
+        assert_eq!(nicknames("top:50%"), vec!["GuardAlpha", "GuardBeta"]);
+        assert!(FindFilter::from_str("measured:maybe").is_err());
+        assert!(FindFilter::from_str("w>:heavy").is_err());
+    }
+
+    #[test]
+    fn family_filters() {
+        // ExitOne and ExitTwo list each other, GuardBeta lists GuardAlpha
+        // which doesn't list it back.
+        let exit_two = "5DF75E9152A63D5C5331BCD1FDB8F4499B9B1685";
+        let guard_alpha = "$0A6F1A9A8F1FF2D7F59680C6427772EC026CBF64";
+        assert_eq!(nicknames(&format!("fam:{}", exit_two)), vec!["ExitOne"]);
+        assert_eq!(
+            nicknames(&format!("fam:{}", guard_alpha)),
+            vec!["GuardBeta"]
+        );
+        assert_eq!(
+            nicknames(&format!("mutualfam:{}", exit_two)),
+            vec!["ExitOne"]
+        );
+        assert!(nicknames(&format!("mutualfam:{}", guard_alpha)).is_empty());
+        assert_eq!(
+            nicknames("famsize>:0"),
+            vec!["ExitOne", "ExitTwo", "GuardBeta"]
+        );
+        assert!(FindFilter::from_str("fam:GuardAlpha").is_err());
+    }
+
+    #[test]
+    fn port_policy_allows_rejects() {
+        assert_eq!(nicknames("allows:25"), vec!["BadOne", "ExitTwo"]);
+        assert_eq!(nicknames("allows:20-23"), vec!["BadOne", "ExitOne"]);
+        assert_eq!(nicknames("allows6:443"), vec!["ExitOne"]);
+        assert_eq!(
+            nicknames("rejects:25"),
+            vec![
+                "ExitOne",
+                "GuardAlpha",
+                "GuardBeta",
+                "MiddleOnlyRelay",
+                "nestor00patof"
+            ]
+        );
+        assert_eq!(nicknames("rejects:8000-9000").len(), 5);
+        assert!(FindFilter::from_str("allows:443-80").is_err());
+        assert!(FindFilter::from_str("allows:http").is_err());
+    }
+
+    #[test]
+    fn country_filter() {
+        geoip::configure_testdata();
+        let filter = FindFilter::from_str("cc:us").unwrap();
+        filter.load().unwrap();
+        assert_eq!(nicknames("cc:us"), ["ExitOne", "ExitTwo"]);
+        assert_eq!(nicknames("cc:NL"), ["ExitOne"]);
+        assert_eq!(
+            nicknames("country:de"),
+            ["BadOne", "MiddleOnlyRelay", "nestor00patof"]
+        );
+        assert!(nicknames("cc:jp").is_empty());
+    }
+
+    #[test]
+    fn asn_filters() {
+        asn::configure_testdata();
+        assert_eq!(nicknames("as:AS64497"), ["ExitOne", "ExitTwo"]);
+        assert_eq!(nicknames("as:64498"), ["GuardAlpha", "GuardBeta"]);
+        assert_eq!(nicknames("asname:exits r us"), ["ExitOne", "ExitTwo"]);
+        assert!(nicknames("as:1").is_empty());
+        assert!(FindFilter::from_str("as:cloud").is_err());
+    }
+
+    #[test]
+    fn history_filters() {
+        history::configure_testdata();
+        FindFilter::from_str("firstseen>:2023-05-01")
+            .unwrap()
+            .load()
+            .unwrap();
+        assert_eq!(nicknames("firstseen>:2023-05-01").len(), 7);
+        assert!(nicknames("firstseen>:2023-05-01T12:00:00").is_empty());
+        assert_eq!(nicknames("lastseen<:2023-05-01T13:00:00"), ["BadOne"]);
+        assert_eq!(nicknames("lastseen>=:2023-05-01T13:00:00").len(), 6);
+        assert!(FindFilter::from_str("firstseen>:last week").is_err());
+    }
+
+    #[test]
+    fn descriptor_filters() {
+        descriptors::configure_testdata();
+        FindFilter::from_str("platform:linux")
+            .unwrap()
+            .load()
+            .unwrap();
+        assert_eq!(
+            nicknames("platform:linux"),
+            ["ExitOne", "GuardAlpha", "nestor00patof"]
+        );
+        assert_eq!(nicknames("platform:windows"), ["GuardBeta"]);
+        assert_eq!(nicknames("uptime<:1day"), ["GuardBeta", "nestor00patof"]);
+        assert_eq!(nicknames("uptime>=:864000"), ["ExitOne", "GuardAlpha"]);
+        assert_eq!(nicknames("bw>:50MB"), ["ExitOne", "GuardAlpha"]);
+        assert_eq!(nicknames("bwrate<:2MB"), ["nestor00patof"]);
+        // Relays without a descriptor never match, unless excluded.
+        assert_eq!(nicknames("platform-:tor"), ["BadOne", "MiddleOnlyRelay"]);
+        assert!(FindFilter::from_str("uptime>:forever").is_err());
+    }
+
+    #[test]
+    fn contact_filters() {
+        descriptors::configure_testdata();
+        FindFilter::from_str("contact:x").unwrap().load().unwrap();
+        assert_eq!(
+            nicknames("contact:EXAMPLE DOT"),
+            ["GuardAlpha", "nestor00patof"]
+        );
+        assert_eq!(nicknames("contactr:^Alice <"), ["GuardAlpha"]);
+        assert_eq!(nicknames("ciiss:ciissversion"), ["ExitOne", "ExitTwo"]);
+        assert_eq!(
+            nicknames("ciiss:email=admin@exitsrus"),
+            ["ExitOne", "ExitTwo"]
+        );
+        // The abuse field of a contact which isn't CIISS.
+        assert!(nicknames("ciiss:abuse").is_empty());
+        assert!(FindFilter::from_str("contactr:(").is_err());
+    }
+
+    #[test]
+    fn exclusion_from_str() {
+        let filter = FindFilter::from_str("fl-:exit").unwrap();
+        assert!(filter.exclude);
+        assert!(matches!(filter.filter, Filter::Flags(_)));
+        let filter = FindFilter::from_str("nr:^a-:b$").unwrap();
+        assert!(!filter.exclude);
+        assert!(matches!(
+            &filter.filter,
+            Filter::NicknameRegex(re) if re.as_str() == "^a-:b$"
+        ));
+        assert_eq!(
+            nicknames("ni-:exit"),
+            [
+                "BadOne",
+                "GuardAlpha",
+                "GuardBeta",
+                "MiddleOnlyRelay",
+                "nestor00patof"
+            ]
+        );
+        assert_eq!(nicknames("w<-:10000"), ["GuardAlpha", "GuardBeta"]);
+    }
+
+    #[test]
+    fn nickname_regex_invalid() {
+        assert!(matches!(
+            FindFilter::from_str("nr:Guard("),
+            Err(Error::WrongRegex(_))
+        ));
+    }
     #[test]
     fn port_policy_filter_from_str() {
         let filter_str = "pp:accept 20-23,43,53,79-81,88,110,143,194,220";
@@ -0,0 +1,157 @@
+//! Country lookups using tor's GeoIP databases.
+//!
+//! The databases are the `geoip` and `geoip6` files shipped with tor, which
+//! map ranges of addresses to country codes, eg. `16777216,16777471,AU` for
+//! IPv4, where the addresses are integers, and
+//! `2001:200::,2001:200:ffff:ffff:ffff:ffff:ffff:ffff,JP` for IPv6.
+//!
+//! They're loaded from local files, once and only when needed.
+
+use std::fs::read_to_string;
+use std::net::{IpAddr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::commands::err::Error;
+
+static PATHS: OnceLock<(PathBuf, PathBuf)> = OnceLock::new();
+static GEOIP: OnceLock<GeoIp> = OnceLock::new();
+
+/// Address ranges, sorted by their lowest address, and their country code.
+#[derive(Debug, Default)]
+pub struct GeoIp {
+    v4: Vec<(u32, u32, String)>,
+    v6: Vec<(u128, u128, String)>,
+}
+
+/// Parse the lines of a database, skipping the comments.
+fn parse_lines<T, F>(
+    path: &Path,
+    parse_ip: F,
+) -> Result<Vec<(T, T, String)>, Error>
+where
+    T: Ord,
+    F: Fn(&str) -> Option<T>,
+{
+    let content = read_to_string(path)?;
+    let mut ranges = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut parts = line.split(',');
+            match (
+                parts.next().and_then(&parse_ip),
+                parts.next().and_then(&parse_ip),
+                parts.next(),
+            ) {
+                (Some(lo), Some(hi), Some(cc)) => {
+                    Ok((lo, hi, cc.to_uppercase()))
+                }
+                _ => Err(Error::InvalidDatabase(line.to_string())),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    ranges.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(ranges)
+}
+
+/// Find the value of the range containing `ip`, in ranges sorted by their
+/// lowest address.
+pub fn lookup<T: Ord + Copy, V>(ranges: &[(T, T, V)], ip: T) -> Option<&V> {
+    // The index of the first range starting after `ip`.
+    let i = ranges.partition_point(|(lo, _, _)| *lo <= ip);
+    let (_, hi, value) = ranges.get(i.checked_sub(1)?)?;
+    (ip <= *hi).then_some(value)
+}
+
+impl GeoIp {
+    pub fn load(geoip: &Path, geoip6: &Path) -> Result<Self, Error> {
+        Ok(GeoIp {
+            v4: parse_lines(geoip, |ip| ip.parse::<u32>().ok())?,
+            v6: parse_lines(geoip6, |ip| {
+                ip.parse::<Ipv6Addr>().ok().map(u128::from)
+            })?,
+        })
+    }
+
+    /// Return the country code of the address, if it's in the database.
+    pub fn country(&self, ip: IpAddr) -> Option<&str> {
+        match ip {
+            IpAddr::V4(ip) => lookup(&self.v4, u32::from(ip)),
+            IpAddr::V6(ip) => lookup(&self.v6, u128::from(ip)),
+        }
+        .map(String::as_str)
+    }
+}
+
+/// Set the paths of the IPv4 and IPv6 databases.
+pub fn configure(geoip: &Path, geoip6: &Path) {
+    // Only the first configuration is kept.
+    let _ = PATHS.set((geoip.to_path_buf(), geoip6.to_path_buf()));
+}
+
+/// Load the databases from the configured paths, if not already loaded.
+pub fn load() -> Result<&'static GeoIp, Error> {
+    if let Some(geoip) = GEOIP.get() {
+        return Ok(geoip);
+    }
+    let (geoip, geoip6) = PATHS
+        .get()
+        .ok_or_else(|| Error::MissingDatabase("GeoIP".to_string()))?;
+    let loaded = GeoIp::load(geoip, geoip6)?;
+    Ok(GEOIP.get_or_init(|| loaded))
+}
+
+/// Return the databases if they're loaded.
+pub fn get() -> Option<&'static GeoIp> {
+    GEOIP.get()
+}
+
+/// Return the country codes of the relay's ORPort addresses.
+pub fn relay_countries(relay: &tor_netdir::Relay) -> Vec<&'static str> {
+    let mut countries: Vec<_> = relay
+        .rs()
+        .orport_addrs()
+        .filter_map(|addr| get()?.country(addr.ip()))
+        .collect();
+    countries.sort();
+    countries.dedup();
+    countries
+}
+
+/// Configure the databases in `testdata/`, for tests.
+#[cfg(test)]
+pub fn configure_testdata() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    configure(&root.join("testdata/geoip"), &root.join("testdata/geoip6"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_countries() {
+        configure_testdata();
+        let geoip = load().unwrap();
+        let country = |ip: &str| geoip.country(ip.parse().unwrap());
+        assert_eq!(country("192.0.2.0"), Some("DE"));
+        assert_eq!(country("192.0.2.255"), Some("DE"));
+        assert_eq!(country("198.51.100.7"), Some("US"));
+        assert_eq!(country("192.0.3.1"), None);
+        assert_eq!(country("1.1.1.1"), None);
+        assert_eq!(country("2001:db8::1"), Some("NL"));
+        assert_eq!(country("2001:db9::1"), None);
+    }
+
+    #[test]
+    fn load_invalid_database() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let result = GeoIp::load(
+            &root.join("testdata/geoip6"),
+            &root.join("testdata/geoip6"),
+        );
+        assert!(matches!(result, Err(Error::InvalidDatabase(_))));
+    }
+}
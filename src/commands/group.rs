@@ -0,0 +1,267 @@
+//! Grouping of relays by one of their attributes.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::commands::err::Error;
+use crate::commands::weight::{self, WeightKind};
+use crate::commands::{asn, contact, descriptors, find, geoip, util};
+
+/// The attribute to group relays by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    /// The tor version of the relay.
+    Version,
+    /// Each of the relay flags.
+    Flag,
+    /// The countries of the relay addresses.
+    Country,
+    /// The Autonomous Systems of the relay addresses.
+    Asn,
+    /// The ORPorts of the relay.
+    Port,
+    /// The /24 networks of the relay IPv4 addresses.
+    Net24,
+    /// The IPv4 exit policy summary of the relay microdescriptor, as in the
+    /// `pp` filter.
+    Policy,
+    /// The operator of the relay, from its contact: the CIISS email or url,
+    /// or else the whole contact.
+    Contact,
+}
+
+impl FromStr for GroupBy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "version" | "v" => Ok(GroupBy::Version),
+            "flag" | "fl" => Ok(GroupBy::Flag),
+            "country" | "cc" => Ok(GroupBy::Country),
+            "asn" | "as" => Ok(GroupBy::Asn),
+            "port" | "p" => Ok(GroupBy::Port),
+            "/24" | "net24" => Ok(GroupBy::Net24),
+            "policy" | "pp" => Ok(GroupBy::Policy),
+            "contact" => Ok(GroupBy::Contact),
+            _ => Err(Error::UnrecognizedGroup(s.to_string())),
+        }
+    }
+}
+
+/// The key of the relays that aren't in any group.
+const UNKNOWN: &str = "unknown";
+
+impl GroupBy {
+    /// Load the databases the grouping needs, before grouping any relay.
+    pub fn load(&self) -> Result<(), Error> {
+        match self {
+            GroupBy::Country => geoip::load().map(|_| ()),
+            GroupBy::Asn => asn::load().map(|_| ()),
+            GroupBy::Contact => descriptors::load().map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Return the keys of the groups the relay belongs to.
+    fn keys(&self, relay: &tor_netdir::Relay) -> Vec<String> {
+        let mut keys: Vec<String> = match self {
+            GroupBy::Version => vec![util::get_version(relay)],
+            GroupBy::Flag => util::flag_names(*relay.rs().flags())
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            GroupBy::Country => geoip::relay_countries(relay)
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            GroupBy::Asn => asn::relay_ases(relay)
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            GroupBy::Port => relay
+                .rs()
+                .orport_addrs()
+                .map(|addr| addr.port().to_string())
+                .collect(),
+            GroupBy::Net24 => relay
+                .rs()
+                .orport_addrs()
+                .filter_map(|addr| match addr.ip() {
+                    IpAddr::V4(ip) => {
+                        let [a, b, c, _] = ip.octets();
+                        Some(format!("{a}.{b}.{c}.0/24"))
+                    }
+                    IpAddr::V6(_) => None,
+                })
+                .collect(),
+            GroupBy::Policy => vec![find::md_policy(relay, false).to_string()],
+            GroupBy::Contact => contact::operator(relay).into_iter().collect(),
+        };
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+}
+
+/// The relays in a group.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Group {
+    pub key: String,
+    pub count: usize,
+    /// The consensus weight of the relays.
+    pub weight: u64,
+    /// The fraction of the network consensus weight, from 0 to 1.
+    pub weight_fraction: f64,
+}
+
+/// Group the relays, from the largest group to the smallest. A relay is
+/// counted in every group it belongs to, eg. in every one of its flags.
+pub fn group(
+    netdir: &tor_netdir::NetDir,
+    relays: &[tor_netdir::Relay],
+    by: GroupBy,
+) -> Vec<Group> {
+    let mut groups: HashMap<String, (usize, u64)> = HashMap::new();
+    for relay in relays {
+        let mut keys = by.keys(relay);
+        if keys.is_empty() {
+            keys.push(UNKNOWN.to_string());
+        }
+        let weight =
+            weight::relay_weight(netdir, relay, WeightKind::Consensus);
+        for key in keys {
+            let group = groups.entry(key).or_default();
+            group.0 += 1;
+            group.1 += weight;
+        }
+    }
+    let total = weight::total_weight(netdir, WeightKind::Consensus);
+    let mut groups: Vec<_> = groups
+        .into_iter()
+        .map(|(key, (count, weight))| Group {
+            key,
+            count,
+            weight,
+            weight_fraction: if total == 0 {
+                0.0
+            } else {
+                weight as f64 / total as f64
+            },
+        })
+        .collect();
+    groups.sort_by(|a, b| b.count.cmp(&a.count).then(a.key.cmp(&b.key)));
+    groups
+}
+
+/// The width of the largest bar of a histogram.
+const BAR_WIDTH: usize = 30;
+
+/// Print the groups as a histogram of their relay counts.
+pub fn print_histogram(groups: &[Group], indent: usize) {
+    let max = groups.iter().map(|g| g.count).max().unwrap_or(0);
+    for group in groups {
+        let bar = "#".repeat((group.count * BAR_WIDTH).div_ceil(max.max(1)));
+        println!(
+            "{:indent$}{:>6} {:>7.2}%  {:<BAR_WIDTH$}  {}",
+            "",
+            group.count,
+            group.weight_fraction * 100.0,
+            bar,
+            group.key,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::consensus;
+
+    fn groups(by: &str) -> Vec<(String, usize, u64)> {
+        let netdir = consensus::test_netdir();
+        let relays: Vec<_> = netdir.relays().collect();
+        group(&netdir, &relays, by.parse().unwrap())
+            .into_iter()
+            .map(|g| (g.key, g.count, g.weight))
+            .collect()
+    }
+
+    #[test]
+    fn group_by_asn() {
+        asn::configure_testdata();
+        assert_eq!(
+            groups("asn"),
+            [
+                ("AS64496 EXAMPLE-HOSTING".to_string(), 3, 845),
+                ("AS64497 EXITS-R-US - Exits R Us".to_string(), 2, 16000),
+                ("AS64498 GUARDNET".to_string(), 2, 35000),
+            ]
+        );
+    }
+
+    #[test]
+    fn group_by_contact() {
+        descriptors::configure_testdata();
+        assert_eq!(
+            groups("contact"),
+            [
+                ("unknown".to_string(), 3, 15800),
+                ("admin@exitsrus.example".to_string(), 2, 16000),
+                ("Alice <alice AT example DOT org>".to_string(), 1, 20000),
+                (
+                    "nestor <nestor AT example DOT net> abuse:abuse[]example.net"
+                        .to_string(),
+                    1,
+                    45
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn group_by_net24() {
+        assert_eq!(
+            groups("/24"),
+            [
+                ("192.0.2.0/24".to_string(), 3, 845),
+                ("198.51.100.0/24".to_string(), 2, 16000),
+                ("203.0.113.0/24".to_string(), 2, 35000),
+            ]
+        );
+    }
+
+    #[test]
+    fn group_by_flag() {
+        let groups = groups("flag");
+        assert_eq!(groups[0], ("Running".to_string(), 7, 51845));
+        assert_eq!(groups[1], ("Valid".to_string(), 7, 51845));
+        assert_eq!(groups[2], ("Fast".to_string(), 6, 51800));
+        assert!(groups.contains(&("Exit".to_string(), 3, 16300)));
+        assert!(groups.contains(&("Guard".to_string(), 2, 35000)));
+    }
+
+    #[test]
+    fn group_by_port() {
+        // ExitOne is counted once for both its IPv4 and IPv6 ORPorts.
+        assert_eq!(groups("port")[0], ("9001".to_string(), 3, 16500));
+    }
+
+    #[test]
+    fn weight_fractions() {
+        let netdir = consensus::test_netdir();
+        let relays: Vec<_> = netdir.relays().collect();
+        let groups = group(&netdir, &relays, GroupBy::Version);
+        assert_eq!(groups.len(), 7);
+        let total: f64 = groups.iter().map(|g| g.weight_fraction).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parse_group_by() {
+        assert_eq!("/24".parse::<GroupBy>().unwrap(), GroupBy::Net24);
+        assert_eq!("Version".parse::<GroupBy>().unwrap(), GroupBy::Version);
+        assert!("nickname".parse::<GroupBy>().is_err());
+    }
+}
@@ -0,0 +1,229 @@
+//! A local history of the relays, stored in a SQLite database.
+//!
+//! Every ingested consensus records its relays: their fingerprint, nickname,
+//! addresses, flags, version and consensus weight. The filters and columns
+//! then use when each relay was first and last seen.
+
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::commands::err::Error;
+use crate::commands::util;
+
+use tor_netdir::{NetDir, Relay};
+
+static PATH: OnceLock<PathBuf> = OnceLock::new();
+static SEEN: OnceLock<HashMap<String, Seen>> = OnceLock::new();
+
+static SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS consensuses (
+    valid_after INTEGER PRIMARY KEY
+);
+CREATE TABLE IF NOT EXISTS relays (
+    valid_after INTEGER NOT NULL,
+    fingerprint TEXT NOT NULL,
+    nickname TEXT NOT NULL,
+    addresses TEXT NOT NULL,
+    flags TEXT NOT NULL,
+    version TEXT NOT NULL,
+    weight INTEGER NOT NULL,
+    PRIMARY KEY (valid_after, fingerprint)
+);
+";
+
+/// When a relay was first and last seen in the ingested consensuses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Seen {
+    pub first: SystemTime,
+    pub last: SystemTime,
+}
+
+fn to_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+fn from_secs(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+/// Return the fingerprint of the relay as stored in the history.
+pub fn fingerprint(relay: &Relay) -> String {
+    hex::encode_upper(relay.rsa_id().as_bytes())
+}
+
+/// Parse a time of a filter, either a date, eg. `2023-05-01`, or a date and
+/// a time, eg. `2023-05-01 12:00:00`.
+pub fn parse_time(s: &str) -> Result<SystemTime, Error> {
+    humantime::parse_rfc3339_weak(s)
+        .or_else(|_| humantime::parse_rfc3339_weak(&format!("{s} 00:00:00")))
+        .map_err(|_| Error::InvalidFilter(format!("Invalid time: {s}")))
+}
+
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    /// Open the database, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(History { conn })
+    }
+
+    /// Record the relays of the consensus, returning how many were recorded
+    /// or nothing if the consensus was already ingested.
+    pub fn ingest(&mut self, netdir: &NetDir) -> Result<Option<usize>, Error> {
+        let valid_after = to_secs(netdir.lifetime().valid_after());
+        let tx = self.conn.transaction()?;
+        let inserted = tx.execute(
+            "INSERT OR IGNORE INTO consensuses (valid_after) VALUES (?1)",
+            params![valid_after],
+        )?;
+        if inserted == 0 {
+            return Ok(None);
+        }
+        let mut count = 0;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO relays (valid_after, fingerprint, \
+                 nickname, addresses, flags, version, weight) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            for relay in netdir.relays() {
+                stmt.execute(params![
+                    valid_after,
+                    fingerprint(&relay),
+                    relay.rs().nickname().to_string(),
+                    util::get_orports(&relay),
+                    util::flag_names(*relay.rs().flags()).join(" "),
+                    util::get_version(&relay),
+                    util::get_weight(&relay).0,
+                ])?;
+                count += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(Some(count))
+    }
+
+    /// Return when every relay was first and last seen, by fingerprint.
+    pub fn seen(&self) -> Result<HashMap<String, Seen>, Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT fingerprint, MIN(valid_after), MAX(valid_after) \
+             FROM relays GROUP BY fingerprint",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                Seen {
+                    first: from_secs(row.get(1)?),
+                    last: from_secs(row.get(2)?),
+                },
+            ))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+/// Set the path of the database.
+pub fn configure(path: &Path) {
+    // Only the first configuration is kept.
+    let _ = PATH.set(path.to_path_buf());
+}
+
+/// Open the configured database, failing when none was given.
+pub fn open() -> Result<History, Error> {
+    let path = PATH
+        .get()
+        .ok_or_else(|| Error::MissingDatabase("history".to_string()))?;
+    History::open(path)
+}
+
+/// Load when the relays were seen from the configured database, if not
+/// already loaded.
+pub fn load() -> Result<&'static HashMap<String, Seen>, Error> {
+    if let Some(seen) = SEEN.get() {
+        return Ok(seen);
+    }
+    let seen = open()?.seen()?;
+    Ok(SEEN.get_or_init(|| seen))
+}
+
+/// Return when the relay was first and last seen, if it's in the loaded
+/// history.
+pub fn relay_seen(relay: &Relay) -> Option<Seen> {
+    SEEN.get()?.get(&fingerprint(relay)).copied()
+}
+
+/// Create a database in a temporary directory from the CollecTor archive in
+/// `testdata/`, for tests.
+#[cfg(test)]
+pub fn configure_testdata() {
+    use crate::commands::collector;
+    use std::sync::Once;
+
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let path = std::env::temp_dir()
+            .join(format!("margot-history-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut history = History::open(&path).unwrap();
+        let archive = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/collector");
+        collector::for_each_netdir(&archive, |netdir| {
+            history.ingest(&netdir)?;
+            Ok(())
+        })
+        .unwrap();
+        configure(&path);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::consensus;
+
+    #[test]
+    fn ingest_and_seen() {
+        let mut history = History::open(Path::new(":memory:")).unwrap();
+        let netdir = consensus::test_netdir();
+        assert_eq!(history.ingest(&netdir).unwrap(), Some(7));
+        // Already ingested.
+        assert_eq!(history.ingest(&netdir).unwrap(), None);
+
+        let seen = history.seen().unwrap();
+        assert_eq!(seen.len(), 7);
+        let valid_after = netdir.lifetime().valid_after();
+        let exit = &seen["03CE4B4816FE36618D586B976F932775E2C19613"];
+        assert_eq!(exit.first, valid_after);
+        assert_eq!(exit.last, valid_after);
+    }
+
+    #[test]
+    fn seen_in_archive() {
+        configure_testdata();
+        let seen = load().unwrap();
+        let first = parse_time("2023-05-01 12:00:00").unwrap();
+        let last = parse_time("2023-05-01 13:00:00").unwrap();
+        // BadOne is gone from the second consensus.
+        let bad = &seen["E8F6B0BC19C736D2755677A7BA71E4C5FB245B1B"];
+        assert_eq!((bad.first, bad.last), (first, first));
+        let exit = &seen["03CE4B4816FE36618D586B976F932775E2C19613"];
+        assert_eq!((exit.first, exit.last), (first, last));
+    }
+
+    #[test]
+    fn time_from_str() {
+        assert_eq!(
+            parse_time("2023-05-01").unwrap(),
+            parse_time("2023-05-01 00:00:00").unwrap()
+        );
+        assert!(parse_time("yesterday").is_err());
+    }
+}
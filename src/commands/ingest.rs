@@ -0,0 +1,55 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use crate::commands::collector;
+use crate::commands::history::{self, History};
+use crate::commands::RunnableOffline;
+
+#[derive(StructOpt)]
+pub struct IngestCommand {
+    /// Ingest every consensus of these CollecTor archives instead of the
+    /// current one.
+    #[structopt(parse(from_os_str))]
+    archives: Vec<PathBuf>,
+}
+
+fn ingest(history: &mut History, netdir: &tor_netdir::NetDir) -> Result<()> {
+    let valid_after =
+        humantime::format_rfc3339_seconds(netdir.lifetime().valid_after());
+    match history.ingest(netdir)? {
+        Some(count) => {
+            println!("[+] Ingested {count} relays of consensus {valid_after}")
+        }
+        None => println!("[-] Consensus {valid_after} already ingested"),
+    }
+    Ok(())
+}
+
+impl IngestCommand {
+    fn ingest_archives(&self) -> Result<()> {
+        let mut history = history::open()?;
+        for archive in &self.archives {
+            collector::for_each_netdir(archive, |netdir| {
+                ingest(&mut history, &netdir)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Ingest the archives, if any, which needs no current consensus.
+    pub fn run_archives(&self) -> Option<Result<()>> {
+        (!self.archives.is_empty()).then(|| self.ingest_archives())
+    }
+}
+
+#[async_trait]
+impl RunnableOffline for IngestCommand {
+    fn run(&self, netdir: &tor_netdir::NetDir) -> Result<()> {
+        if let Some(result) = self.run_archives() {
+            return result;
+        }
+        ingest(&mut history::open()?, netdir)
+    }
+}
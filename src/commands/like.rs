@@ -1,47 +1,257 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use levenshtein::levenshtein;
-use std::collections::HashMap;
 use std::fmt;
+use std::path::PathBuf;
 use structopt::StructOpt;
 
+use crate::commands::confusables::{self, Known};
+use crate::commands::output::{self, Columns, Format};
+use crate::commands::weight::Totals;
 use crate::commands::RunnableOffline;
 
 #[derive(StructOpt)]
 pub struct LikeCommand {
-    name: String,
+    #[structopt(short = "l", long = "oneline")]
+    oneline: bool,
+    /// Output format: text, oneline, json, ndjson, csv, tsv or fps.
+    #[structopt(short = "f", long = "format", default_value = "text")]
+    format: Format,
+    /// Comma separated columns for the oneline, csv and tsv formats, printed
+    /// after the distance.
+    #[structopt(short = "c", long = "columns")]
+    columns: Option<Columns>,
+    /// The number of closest relays printed, 5 by default.
+    #[structopt(short = "n", long = "top")]
+    top: Option<usize>,
+    /// Only print the relays whose nickname is at most this distance away.
+    #[structopt(long = "max-distance")]
+    max_distance: Option<usize>,
+    /// Compare the nicknames' skeletons, where the lookalike characters such
+    /// as `0` and `o` or `1`, `l` and `I` are the same, ignoring their
+    /// numbering.
+    #[structopt(long = "confusables")]
+    confusables: bool,
+    /// Print the relays impersonating the directory authorities, or the
+    /// relays of `--known`: their nickname looks like theirs but they have
+    /// another fingerprint. `--max-distance` is 0 by default.
+    #[structopt(long = "impersonation")]
+    impersonation: bool,
+    /// A file of well-known relays checked by `--impersonation`, one
+    /// `<nickname> [fingerprint]` per line.
+    #[structopt(
+        long = "known",
+        parse(from_os_str),
+        requires = "impersonation"
+    )]
+    known: Option<PathBuf>,
+    #[structopt(required_unless = "impersonation")]
+    name: Option<String>,
 }
 
 impl fmt::Display for LikeCommand {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.name)
+        write!(f, "{}", self.name.as_deref().unwrap_or_default())
     }
 }
 
+/// Return the relays sorted by the distance of their nickname to `name`,
+/// then by nickname, keeping the `top` closest ones within `max_distance`.
+/// With `lookalike`, the distance is the one of the nicknames' skeletons.
+fn closest<'a>(
+    netdir: &'a tor_netdir::NetDir,
+    name: &str,
+    top: usize,
+    max_distance: Option<usize>,
+    lookalike: bool,
+) -> Vec<(usize, tor_netdir::Relay<'a>)> {
+    let distance: fn(&str, &str) -> usize = if lookalike {
+        confusables::distance
+    } else {
+        levenshtein
+    };
+    let mut distances: Vec<_> = netdir
+        .relays()
+        .map(|relay| (distance(name, relay.rs().nickname()), relay))
+        .filter(|(distance, _)| {
+            max_distance.is_none_or(|max| *distance <= max)
+        })
+        .collect();
+    distances.sort_by(|a, b| {
+        a.0.cmp(&b.0)
+            .then(a.1.rs().nickname().cmp(b.1.rs().nickname()))
+    });
+    distances.truncate(top);
+    distances
+}
+
+/// Return the relays impersonating one of the `known` relays, with the
+/// nickname of the impersonated one, sorted by it then by nickname.
+fn impersonations<'a>(
+    netdir: &'a tor_netdir::NetDir,
+    known: &[Known],
+    max_distance: usize,
+) -> Vec<(String, tor_netdir::Relay<'a>)> {
+    let mut impersonations: Vec<_> = netdir
+        .relays()
+        .flat_map(|relay| {
+            known
+                .iter()
+                .filter(|k| {
+                    k.is_impersonated_by(
+                        relay.rs().nickname(),
+                        relay.rsa_id(),
+                        max_distance,
+                    )
+                })
+                .map(|k| (k.nickname.clone(), relay.clone()))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    impersonations.sort_by(|a, b| {
+        a.0.cmp(&b.0)
+            .then(a.1.rs().nickname().cmp(b.1.rs().nickname()))
+    });
+    impersonations
+}
+
 #[async_trait]
 impl RunnableOffline for LikeCommand {
     fn run(&self, netdir: &tor_netdir::NetDir) -> Result<()> {
-        println!("[+] Getting relays from consensus");
-        let relays = netdir.relays();
-        let mut distances = HashMap::new();
-        println!("[+] Computing nickname distances...");
-        let mut count = 0;
-        for relay in relays {
-            count += 1;
-            if (count % 1000) == 0 {
-                println!(" :: {} relays processed", count);
+        let columns = self.columns.clone().unwrap_or_default();
+        columns.load()?;
+        let totals = Totals::new(netdir);
+        let format = if self.oneline {
+            Format::Oneline
+        } else {
+            self.format
+        };
+
+        if self.impersonation {
+            if self.top.is_some() || self.confusables {
+                eprintln!(
+                    "[-] --top and --confusables are ignored with --impersonation"
+                );
+            }
+            let mut known = confusables::authorities(netdir);
+            if let Some(path) = &self.known {
+                known.extend(confusables::known_from_file(path)?);
             }
-            distances.insert(
-                relay.rsa_id().to_string(),
-                levenshtein(self.name.as_str(), relay.rs().nickname()),
+            let relays =
+                impersonations(netdir, &known, self.max_distance.unwrap_or(0));
+            if !format.is_machine() {
+                println!(
+                    "[+] {} relays impersonating {} well-known relays",
+                    relays.len(),
+                    known.len()
+                );
+            }
+            return output::print_keyed_relays(
+                netdir,
+                &totals,
+                &relays,
+                ("impersonates", "Impersonates"),
+                format,
+                &columns,
+                0,
             );
         }
-        println!("[+] Top 5 closest nicknames to: {}", self.name);
-        let mut d_vec: Vec<(&String, &usize)> = distances.iter().collect();
-        d_vec.sort_by(|a, b| a.1.cmp(b.1));
-        for item in d_vec.iter().take(5) {
-            println!(" -> {}: {}", item.0, item.1);
+
+        let name = self.name.as_deref().unwrap_or_default();
+        let distances = closest(
+            netdir,
+            name,
+            self.top.unwrap_or(5),
+            self.max_distance,
+            self.confusables,
+        );
+        if !format.is_machine() {
+            println!(
+                "[+] Top {} closest nicknames to: {}",
+                distances.len(),
+                name
+            );
         }
-        Ok(())
+        output::print_keyed_relays(
+            netdir,
+            &totals,
+            &distances,
+            ("distance", "Distance"),
+            format,
+            &columns,
+            0,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::consensus;
+
+    fn nicknames(
+        name: &str,
+        top: usize,
+        max: Option<usize>,
+        lookalike: bool,
+    ) -> Vec<String> {
+        let netdir = consensus::test_netdir();
+        closest(&netdir, name, top, max, lookalike)
+            .iter()
+            .map(|(_, r)| r.rs().nickname().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn closest_nicknames() {
+        assert_eq!(
+            nicknames("ExitOne", 2, None, false),
+            ["ExitOne", "ExitTwo"]
+        );
+        assert_eq!(
+            nicknames("GuardGamma", 7, Some(5), false),
+            ["GuardAlpha", "GuardBeta"]
+        );
+        assert!(nicknames("Unrelated", 5, Some(0), false).is_empty());
+    }
+
+    #[test]
+    fn confusable_nicknames() {
+        assert!(nicknames("Ex1tOne", 5, Some(0), false).is_empty());
+        assert_eq!(nicknames("Ex1tOne", 5, Some(0), true), ["ExitOne"]);
+        assert_eq!(nicknames("GuardA1pha7", 5, Some(0), true), ["GuardAlpha"]);
+    }
+
+    #[test]
+    fn known_requires_impersonation() {
+        let parse = |args: &[&str]| {
+            LikeCommand::from_iter_safe(["like"].iter().chain(args))
+        };
+        assert!(parse(&["--known", "known.txt", "moria1"]).is_err());
+        assert!(parse(&["--known", "known.txt", "--impersonation"]).is_ok());
+    }
+
+    #[test]
+    fn impersonating_relays() {
+        let netdir = consensus::test_netdir();
+        let alpha = netdir
+            .relays()
+            .find(|r| r.rs().nickname() == "GuardAlpha")
+            .unwrap();
+        let known = [
+            Known {
+                nickname: "Ex1tTwo".to_string(),
+                rsa_id: None,
+            },
+            Known {
+                nickname: "GuardA1pha".to_string(),
+                rsa_id: Some(*alpha.rsa_id()),
+            },
+        ];
+        let found: Vec<_> = impersonations(&netdir, &known, 0)
+            .iter()
+            .map(|(k, r)| (k.clone(), r.rs().nickname().to_string()))
+            .collect();
+        assert_eq!(found, [("Ex1tTwo".to_string(), "ExitTwo".to_string())]);
     }
 }
@@ -0,0 +1,198 @@
+//! Locality-sensitive hashing of relays, to find the near-duplicates of a
+//! whole consensus without comparing every pair of relays.
+//!
+//! Every relay is reduced to a set of shingles of its compared fields, eg.
+//! the character shingles of its
+//! [util::relay2vec](crate::commands::util::relay2vec) fields, and the set to
+//! a MinHash signature: the probability that two signatures agree on a hash
+//! is the Jaccard similarity of the sets. The signatures are split in bands,
+//! and the relays sharing a whole band are the candidate pairs, which are
+//! then compared with the actual distance.
+
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// The length of the shingles, in characters.
+const SHINGLE_LEN: usize = 3;
+/// A Mersenne prime larger than the hashes of the shingles, used by the
+/// permutations.
+const PRIME: u64 = (1 << 61) - 1;
+
+/// The SplitMix64 step, from a state to the next pseudo-random number.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Return the hash of a shingle, the bytes of a value of the field of index
+/// `field`.
+///
+/// The bytes are mixed 8 at a time with SplitMix64 rather than hashed with
+/// a `std` hasher, whose algorithm may change between Rust releases, so
+/// that the signatures are the same across builds.
+pub fn hash(field: usize, bytes: &[u8]) -> u64 {
+    let mut state = field as u64 ^ ((bytes.len() as u64) << 32);
+    let mut hash = splitmix64(&mut state);
+    for chunk in bytes.chunks(8) {
+        let mut word = [0; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        state ^= hash ^ u64::from_le_bytes(word);
+        hash = splitmix64(&mut state);
+    }
+    hash
+}
+
+/// Add the hashes of the character shingles of the text of the field of
+/// index `field` to the set.
+pub fn text_shingles(field: usize, text: &str, shingles: &mut HashSet<u64>) {
+    // The byte offsets of the characters, and of the end of the text.
+    let bounds: Vec<usize> = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([text.len()])
+        .collect();
+    if bounds.len() <= SHINGLE_LEN {
+        shingles.insert(hash(field, text.as_bytes()));
+        return;
+    }
+    for window in bounds.windows(SHINGLE_LEN + 1) {
+        let shingle = &text[window[0]..window[SHINGLE_LEN]];
+        shingles.insert(hash(field, shingle.as_bytes()));
+    }
+}
+
+/// Return the hashes of the shingles of the fields. The shingles of every
+/// field are kept apart, so that eg. the end of a nickname and the start of
+/// an address don't make a shingle.
+pub fn shingles(fields: &[String]) -> HashSet<u64> {
+    let mut shingles = HashSet::new();
+    for (i, field) in fields.iter().enumerate() {
+        text_shingles(i, field, &mut shingles);
+    }
+    shingles
+}
+
+/// The parameters of the index: `bands` bands of `rows` hashes. Two relays
+/// whose shingles have a Jaccard similarity `s` are candidates with a
+/// probability of `1 - (1 - s^rows)^bands`, which is 1/2 around
+/// `(1 / bands)^(1 / rows)`.
+#[derive(Debug, Clone, Copy)]
+pub struct LshParams {
+    pub bands: usize,
+    pub rows: usize,
+}
+
+impl Default for LshParams {
+    /// Relays whose shingles are more than half the same are very likely
+    /// candidates.
+    fn default() -> Self {
+        LshParams { bands: 20, rows: 4 }
+    }
+}
+
+/// The random permutations `(a * x + b) mod PRIME` of the MinHash.
+struct MinHasher {
+    permutations: Vec<(u64, u64)>,
+}
+
+impl MinHasher {
+    fn new(len: usize) -> Self {
+        // A fixed seed, so the signatures are reproducible.
+        let mut state: u64 = 0x5eed;
+        let mut next = || splitmix64(&mut state);
+        let permutations = (0..len)
+            .map(|_| (next() % (PRIME - 1) + 1, next() % PRIME))
+            .collect();
+        MinHasher { permutations }
+    }
+
+    fn signature(&self, shingles: &HashSet<u64>) -> Vec<u64> {
+        self.permutations
+            .iter()
+            .map(|&(a, b)| {
+                shingles
+                    .iter()
+                    .map(|&x| {
+                        let x = (x % PRIME) as u128;
+                        ((a as u128 * x + b as u128) % PRIME as u128) as u64
+                    })
+                    .min()
+                    .unwrap_or(u64::MAX)
+            })
+            .collect()
+    }
+}
+
+/// Return the pairs of indexes `(a, b)`, with `a < b`, of the items sharing
+/// a band of their signatures, from the shingles of every item.
+pub fn candidates(
+    items: &[HashSet<u64>],
+    params: LshParams,
+) -> HashSet<(usize, usize)> {
+    let minhasher = MinHasher::new(params.bands * params.rows);
+    let signatures: Vec<Vec<u64>> = items
+        .par_iter()
+        .map(|shingles| minhasher.signature(shingles))
+        .collect();
+
+    let mut pairs = HashSet::new();
+    for band in 0..params.bands {
+        let mut buckets: HashMap<&[u64], Vec<usize>> = HashMap::new();
+        let rows = band * params.rows..(band + 1) * params.rows;
+        for (i, signature) in signatures.iter().enumerate() {
+            buckets.entry(&signature[rows.clone()]).or_default().push(i);
+        }
+        for bucket in buckets.values().filter(|b| b.len() > 1) {
+            for (n, &a) in bucket.iter().enumerate() {
+                for &b in &bucket[n + 1..] {
+                    pairs.insert((a, b));
+                }
+            }
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn stable_hash() {
+        // The signatures must not change with the Rust release.
+        assert_eq!(hash(0, b"abc"), 0x6e5c6ee9d64628d7);
+        assert_eq!(hash(1, b"abc"), 0x4181c31af4f38ec1);
+        assert_eq!(hash(0, b""), 0xe220a8397b1dcdaf);
+    }
+
+    #[test]
+    fn shingles_per_field() {
+        assert_eq!(shingles(&fields(&["abcd"])).len(), 2);
+        assert_eq!(shingles(&fields(&["ab", "abcd"])).len(), 3);
+        // The same shingle in two fields.
+        assert_eq!(shingles(&fields(&["abc", "abc"])).len(), 2);
+    }
+
+    #[test]
+    fn near_duplicates_are_candidates() {
+        let items: Vec<_> = [
+            fields(&["SybilRelay01", "192.0.2.10443", "7484", "0.4.8.9"]),
+            fields(&["SybilRelay02", "192.0.2.11443", "7484", "0.4.8.9"]),
+            fields(&["GuardAlpha", "203.0.113.10443", "1404", "0.4.8.10"]),
+            fields(&["nestor00patof", "198.51.100.201337", "5", "0.4.7.8"]),
+        ]
+        .iter()
+        .map(|f| shingles(f))
+        .collect();
+        let candidates = candidates(&items, LshParams::default());
+        assert!(candidates.contains(&(0, 1)));
+        assert!(!candidates.contains(&(2, 3)));
+    }
+}
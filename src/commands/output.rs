@@ -0,0 +1,949 @@
+//! Output formats of the relays found by the commands.
+
+use anyhow::Result;
+use prettytable::format as table_format;
+use prettytable::{Row, Table};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use crate::commands::asn;
+use crate::commands::contact;
+use crate::commands::descriptors::{self, ServerDescriptor};
+use crate::commands::err::Error;
+use crate::commands::geoip;
+use crate::commands::history;
+use crate::commands::util;
+use crate::commands::weight::{self, Position, Summary, Totals};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Free-form text, one relay attribute per line.
+    Text,
+    /// A table, one relay per row.
+    Oneline,
+    /// A JSON array.
+    Json,
+    /// Newline delimited JSON, one relay per line.
+    Ndjson,
+    /// Comma separated values, one relay per row.
+    Csv,
+    /// Tab separated values, one relay per row.
+    Tsv,
+    /// The Rsa fingerprints, one per line, as read by the `ff:` filter.
+    Fps,
+}
+
+impl Format {
+    /// Whether the format is meant to be read by programs rather than
+    /// humans, in which case nothing else should be printed.
+    pub fn is_machine(&self) -> bool {
+        matches!(
+            self,
+            Format::Json
+                | Format::Ndjson
+                | Format::Csv
+                | Format::Tsv
+                | Format::Fps
+        )
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Format::Text),
+            "oneline" => Ok(Format::Oneline),
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            "fps" | "fingerprints" => Ok(Format::Fps),
+            _ => Err(Error::UnrecognizedFormat(s.to_string())),
+        }
+    }
+}
+
+/// The relay attributes that [util::describe_relays] prints. The ASes and
+/// the descriptor fields are only there when their database was given.
+#[derive(Debug, Serialize)]
+pub struct RelayInfo {
+    nickname: String,
+    rsa: String,
+    ed25519: String,
+    flags: Vec<&'static str>,
+    weight: u32,
+    measured: bool,
+    /// The fraction of the network consensus weight, from 0 to 1.
+    consensus_fraction: f64,
+    guard_probability: f64,
+    middle_probability: f64,
+    exit_probability: f64,
+    version: String,
+    orports: Vec<String>,
+    ipv4_policy: String,
+    ipv6_policy: String,
+    family: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ases: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    platform: Option<String>,
+    /// The uptime, in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uptime: Option<u64>,
+    /// The average, burst and observed bandwidths, in bytes per second.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bandwidth: Option<[u64; 3]>,
+}
+
+impl RelayInfo {
+    pub fn new(
+        netdir: &tor_netdir::NetDir,
+        totals: &Totals,
+        r: &tor_netdir::Relay,
+    ) -> Self {
+        let (weight, measured) = util::get_weight(r);
+        let probability = |p| weight::probability(netdir, totals, r, p);
+        let desc = descriptors::relay_descriptor(r);
+        Self {
+            nickname: r.rs().nickname().to_string(),
+            rsa: r.rsa_id().to_string().replace('$', "").to_uppercase(),
+            ed25519: r.md().ed25519_id().to_string(),
+            flags: util::flag_names(*r.rs().flags()),
+            weight,
+            measured,
+            consensus_fraction: weight::consensus_fraction(netdir, totals, r),
+            guard_probability: probability(Position::Guard),
+            middle_probability: probability(Position::Middle),
+            exit_probability: probability(Position::Exit),
+            version: util::get_version(r),
+            orports: r.rs().orport_addrs().map(ToString::to_string).collect(),
+            ipv4_policy: r.ipv4_policy().to_string(),
+            ipv6_policy: r.ipv6_policy().to_string(),
+            family: util::get_family(r),
+            ases: asn::get().map(|_| {
+                asn::relay_ases(r).iter().map(ToString::to_string).collect()
+            }),
+            platform: desc.and_then(|desc| desc.platform.clone()),
+            uptime: desc.and_then(|desc| desc.uptime),
+            bandwidth: desc.map(|desc| {
+                [
+                    desc.bandwidth_avg,
+                    desc.bandwidth_burst,
+                    desc.bandwidth_observed,
+                ]
+            }),
+        }
+    }
+
+    /// Load the ASes and the descriptors databases when they were given,
+    /// as [util::describe_relays] does. The errors go to stderr, not to mix
+    /// with the JSON.
+    pub fn load() {
+        if asn::is_configured() {
+            if let Err(e) = asn::load() {
+                eprintln!("[-] {}", e);
+            }
+        }
+        if descriptors::is_configured() {
+            if let Err(e) = descriptors::load() {
+                eprintln!("[-] {}", e);
+            }
+        }
+    }
+}
+
+/// A relay attribute that can be printed as a column.
+pub struct Column {
+    /// The name to select the column and the CSV/TSV header.
+    pub name: &'static str,
+    /// The title of the column in a table.
+    pub title: &'static str,
+    get: fn(&tor_netdir::NetDir, &Totals, &tor_netdir::Relay) -> String,
+    /// Load the database the column needs, if any.
+    load: Option<fn() -> Result<(), Error>>,
+}
+
+/// The registry of columns.
+static COLUMNS: &[Column] = &[
+    Column {
+        name: "nickname",
+        title: "Nickname",
+        get: |_, _, r| r.rs().nickname().to_string(),
+        load: None,
+    },
+    Column {
+        name: "rsa",
+        title: "Rsa",
+        get: |_, _, r| hex::encode_upper(r.rsa_id().as_bytes()),
+        load: None,
+    },
+    Column {
+        name: "ed",
+        title: "Ed",
+        get: |_, _, r| r.md().ed25519_id().to_string(),
+        load: None,
+    },
+    Column {
+        name: "flags",
+        title: "Flags",
+        get: |_, _, r| util::flag_names(*r.rs().flags()).join(" "),
+        load: None,
+    },
+    Column {
+        name: "weight",
+        title: "Weight",
+        get: |_, _, r| util::get_weight(r).0.to_string(),
+        load: None,
+    },
+    Column {
+        name: "measured",
+        title: "Measured",
+        get: |_, _, r| util::get_weight(r).1.to_string(),
+        load: None,
+    },
+    Column {
+        name: "version",
+        title: "Version",
+        get: |_, _, r| util::get_version(r),
+        load: None,
+    },
+    Column {
+        name: "orports",
+        title: "ORPorts",
+        get: |_, _, r| util::get_orports(r),
+        load: None,
+    },
+    Column {
+        name: "policy4",
+        title: "IPv4 Policy",
+        get: |_, _, r| r.ipv4_policy().to_string(),
+        load: None,
+    },
+    Column {
+        name: "policy6",
+        title: "IPv6 Policy",
+        get: |_, _, r| r.ipv6_policy().to_string(),
+        load: None,
+    },
+    Column {
+        name: "family",
+        title: "Family",
+        get: |_, _, r| util::get_family(r).join(" "),
+        load: None,
+    },
+    Column {
+        name: "country",
+        title: "Country",
+        get: |_, _, r| geoip::relay_countries(r).join(" "),
+        load: Some(|| geoip::load().map(|_| ())),
+    },
+    Column {
+        name: "asn",
+        title: "ASN",
+        get: |_, _, r| {
+            let ases = asn::relay_ases(r);
+            let numbers: Vec<_> =
+                ases.iter().map(|a| format!("AS{}", a.number)).collect();
+            numbers.join(" ")
+        },
+        load: Some(|| asn::load().map(|_| ())),
+    },
+    Column {
+        name: "asname",
+        title: "AS Name",
+        get: |_, _, r| {
+            let ases = asn::relay_ases(r);
+            let names: Vec<_> = ases.iter().map(|a| a.name.as_str()).collect();
+            names.join(", ")
+        },
+        load: Some(|| asn::load().map(|_| ())),
+    },
+    Column {
+        name: "firstseen",
+        title: "First Seen",
+        get: |_, _, r| seen_time(r, |seen| seen.first),
+        load: Some(|| history::load().map(|_| ())),
+    },
+    Column {
+        name: "lastseen",
+        title: "Last Seen",
+        get: |_, _, r| seen_time(r, |seen| seen.last),
+        load: Some(|| history::load().map(|_| ())),
+    },
+    Column {
+        name: "age",
+        title: "Age (days)",
+        get: |netdir, _, r| {
+            let valid_after = netdir.lifetime().valid_after();
+            history::relay_seen(r)
+                .and_then(|seen| valid_after.duration_since(seen.first).ok())
+                .map_or(String::new(), |age| {
+                    (age.as_secs() / 86400).to_string()
+                })
+        },
+        load: Some(|| history::load().map(|_| ())),
+    },
+    Column {
+        name: "platform",
+        title: "Platform",
+        get: |_, _, r| {
+            descriptor_field(r, |desc| {
+                desc.platform.clone().unwrap_or_default()
+            })
+        },
+        load: Some(|| descriptors::load().map(|_| ())),
+    },
+    Column {
+        name: "uptime",
+        title: "Uptime",
+        get: |_, _, r| {
+            descriptor_field(r, |desc| {
+                desc.uptime.map_or(String::new(), |u| {
+                    humantime::format_duration(Duration::from_secs(u))
+                        .to_string()
+                })
+            })
+        },
+        load: Some(|| descriptors::load().map(|_| ())),
+    },
+    Column {
+        name: "bwrate",
+        title: "Bandwidth Rate",
+        get: |_, _, r| {
+            descriptor_field(r, |desc| desc.bandwidth_avg.to_string())
+        },
+        load: Some(|| descriptors::load().map(|_| ())),
+    },
+    Column {
+        name: "bwburst",
+        title: "Bandwidth Burst",
+        get: |_, _, r| {
+            descriptor_field(r, |desc| desc.bandwidth_burst.to_string())
+        },
+        load: Some(|| descriptors::load().map(|_| ())),
+    },
+    Column {
+        name: "bwobserved",
+        title: "Observed Bandwidth",
+        get: |_, _, r| {
+            descriptor_field(r, |desc| desc.bandwidth_observed.to_string())
+        },
+        load: Some(|| descriptors::load().map(|_| ())),
+    },
+    Column {
+        name: "contact",
+        title: "Contact",
+        get: |_, _, r| {
+            descriptor_field(r, |desc| {
+                desc.contact.clone().unwrap_or_default()
+            })
+        },
+        load: Some(|| descriptors::load().map(|_| ())),
+    },
+    Column {
+        name: "email",
+        title: "Email",
+        get: |_, _, r| {
+            contact::relay_contact_info(r).email().unwrap_or_default()
+        },
+        load: Some(|| descriptors::load().map(|_| ())),
+    },
+    Column {
+        name: "url",
+        title: "URL",
+        get: |_, _, r| {
+            let info = contact::relay_contact_info(r);
+            info.url().unwrap_or_default().to_string()
+        },
+        load: Some(|| descriptors::load().map(|_| ())),
+    },
+    Column {
+        name: "proof",
+        title: "Proof",
+        get: |_, _, r| {
+            let info = contact::relay_contact_info(r);
+            info.proof().unwrap_or_default().to_string()
+        },
+        load: Some(|| descriptors::load().map(|_| ())),
+    },
+    Column {
+        name: "ciiss",
+        title: "CIISS",
+        get: |_, _, r| {
+            descriptor_field(r, |desc| {
+                let contact = desc.contact.as_deref().unwrap_or_default();
+                contact::ContactInfo::parse(contact).is_ciiss().to_string()
+            })
+        },
+        load: Some(|| descriptors::load().map(|_| ())),
+    },
+    Column {
+        name: "published",
+        title: "Published",
+        get: |_, _, r| {
+            descriptor_field(r, |desc| {
+                humantime::format_rfc3339_seconds(desc.published).to_string()
+            })
+        },
+        load: Some(|| descriptors::load().map(|_| ())),
+    },
+    Column {
+        name: "exitpolicy",
+        title: "Exit Policy",
+        get: |_, _, r| descriptor_field(r, |desc| desc.exit_policy.join(", ")),
+        load: Some(|| descriptors::load().map(|_| ())),
+    },
+    Column {
+        name: "wfraction",
+        title: "Weight %",
+        get: |netdir, totals, r| {
+            percent(weight::consensus_fraction(netdir, totals, r))
+        },
+        load: None,
+    },
+    Column {
+        name: "guardprob",
+        title: "Guard %",
+        get: |netdir, totals, r| {
+            percent(weight::probability(netdir, totals, r, Position::Guard))
+        },
+        load: None,
+    },
+    Column {
+        name: "middleprob",
+        title: "Middle %",
+        get: |netdir, totals, r| {
+            percent(weight::probability(netdir, totals, r, Position::Middle))
+        },
+        load: None,
+    },
+    Column {
+        name: "exitprob",
+        title: "Exit %",
+        get: |netdir, totals, r| {
+            percent(weight::probability(netdir, totals, r, Position::Exit))
+        },
+        load: None,
+    },
+];
+
+/// Format when the relay was seen, empty if it's not in the history.
+fn seen_time(
+    relay: &tor_netdir::Relay,
+    time: fn(history::Seen) -> SystemTime,
+) -> String {
+    history::relay_seen(relay).map_or(String::new(), |seen| {
+        humantime::format_rfc3339_seconds(time(seen)).to_string()
+    })
+}
+
+/// Format a field of the relay descriptor, empty if it has none.
+fn descriptor_field(
+    relay: &tor_netdir::Relay,
+    field: fn(&ServerDescriptor) -> String,
+) -> String {
+    descriptors::relay_descriptor(relay).map_or(String::new(), field)
+}
+
+/// Format a fraction from 0 to 1 as a percentage.
+fn percent(fraction: f64) -> String {
+    format!("{:.4}", fraction * 100.0)
+}
+
+/// The columns printed when none are given.
+static DEFAULT_COLUMNS: &str = "nickname,rsa,ed,version,orports";
+
+/// A list of columns, parsed from their comma separated names.
+#[derive(Clone)]
+pub struct Columns(Vec<&'static Column>);
+
+impl Columns {
+    fn titles(&self) -> Vec<&'static str> {
+        self.0.iter().map(|c| c.title).collect()
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.0.iter().map(|c| c.name).collect()
+    }
+
+    /// Load the databases the columns need, before printing any relay.
+    pub fn load(&self) -> Result<(), Error> {
+        for load in self.0.iter().filter_map(|c| c.load) {
+            load()?;
+        }
+        Ok(())
+    }
+
+    pub fn values(
+        &self,
+        netdir: &tor_netdir::NetDir,
+        totals: &Totals,
+        relay: &tor_netdir::Relay,
+    ) -> Vec<String> {
+        self.0
+            .iter()
+            .map(|c| (c.get)(netdir, totals, relay))
+            .collect()
+    }
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        DEFAULT_COLUMNS.parse().expect("default columns")
+    }
+}
+
+impl FromStr for Columns {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|name| {
+                COLUMNS
+                    .iter()
+                    .find(|c| c.name == name.trim().to_lowercase())
+                    .ok_or_else(|| Error::UnrecognizedColumn(name.to_string()))
+            })
+            .collect::<Result<_, _>>()
+            .map(Columns)
+    }
+}
+
+/// Print rows as a table.
+fn print_rows(titles: Vec<&str>, rows: Vec<Vec<String>>, indent: usize) {
+    let tfmt = table_format::FormatBuilder::new()
+        .column_separator('|')
+        .borders('|')
+        .separators(
+            &[
+                table_format::LinePosition::Top,
+                table_format::LinePosition::Intern,
+                table_format::LinePosition::Title,
+                table_format::LinePosition::Bottom,
+            ],
+            table_format::LineSeparator::new('-', '+', '+', '+'),
+        )
+        .padding(1, 1)
+        .indent(indent)
+        .build();
+    let mut table = Table::new();
+    table.set_format(tfmt);
+    table.set_titles(Row::from(titles));
+    for row in rows {
+        table.add_row(Row::from(row));
+    }
+    table.printstd();
+}
+
+/// Print the relays as a table with the given columns.
+pub fn print_table(
+    netdir: &tor_netdir::NetDir,
+    totals: &Totals,
+    relays: &[tor_netdir::Relay],
+    columns: &Columns,
+    indent: usize,
+) {
+    let rows = relays
+        .iter()
+        .map(|r| columns.values(netdir, totals, r))
+        .collect();
+    print_rows(columns.titles(), rows, indent);
+}
+
+/// Print rows as delimiter separated values.
+fn print_separated(
+    names: Vec<&str>,
+    rows: Vec<Vec<String>>,
+    delimiter: u8,
+) -> Result<()> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(io::stdout());
+    writer.write_record(names)?;
+    for row in rows {
+        writer.write_record(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Print the Rsa fingerprints of the relays, one per line.
+fn print_fingerprints(relays: &[&tor_netdir::Relay]) {
+    for r in relays {
+        println!("{}", r.rsa_id().to_string().replace('$', "").to_uppercase());
+    }
+}
+
+/// Print the relays in the given format.
+///
+/// The columns are used by the formats printing one relay per row.
+pub fn print_relays(
+    netdir: &tor_netdir::NetDir,
+    totals: &Totals,
+    relays: &[tor_netdir::Relay],
+    format: Format,
+    columns: &Columns,
+    indent: usize,
+) -> Result<()> {
+    match format {
+        Format::Text => {
+            util::describe_relays(netdir, totals, relays, false, indent)
+        }
+        Format::Oneline => {
+            print_table(netdir, totals, relays, columns, indent)
+        }
+        Format::Json => {
+            RelayInfo::load();
+            let infos: Vec<_> = relays
+                .iter()
+                .map(|r| RelayInfo::new(netdir, totals, r))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&infos)?);
+        }
+        Format::Ndjson => {
+            RelayInfo::load();
+            for relay in relays {
+                let info = RelayInfo::new(netdir, totals, relay);
+                println!("{}", serde_json::to_string(&info)?);
+            }
+        }
+        Format::Csv | Format::Tsv => {
+            let delimiter = if format == Format::Csv { b',' } else { b'\t' };
+            let rows = relays
+                .iter()
+                .map(|r| columns.values(netdir, totals, r))
+                .collect();
+            print_separated(columns.names(), rows, delimiter)?
+        }
+        Format::Fps => print_fingerprints(&relays.iter().collect::<Vec<_>>()),
+    }
+    Ok(())
+}
+
+/// A distance between relays, printed whole when it's a Levenshtein
+/// distance.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize)]
+#[serde(transparent)]
+pub struct Distance(pub f64);
+
+impl fmt::Display for Distance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", util::format_distance(self.0))
+    }
+}
+
+/// A relay with a value of its own, as printed in the JSON formats.
+#[derive(Serialize)]
+struct KeyedRelayInfo<'a, K: Serialize> {
+    #[serde(flatten)]
+    key: HashMap<&'a str, &'a K>,
+    #[serde(flatten)]
+    relay: RelayInfo,
+}
+
+impl<'a, K: Serialize> KeyedRelayInfo<'a, K> {
+    fn new(
+        netdir: &tor_netdir::NetDir,
+        totals: &Totals,
+        name: &'a str,
+        (key, r): &'a (K, tor_netdir::Relay),
+    ) -> Self {
+        Self {
+            key: HashMap::from([(name, key)]),
+            relay: RelayInfo::new(netdir, totals, r),
+        }
+    }
+}
+
+/// Print the relays, each with a value of its own, eg. its distance to a
+/// reference relay, in the given format. The value is named `name`, or
+/// `title` in the text and table formats, and printed before the columns.
+pub fn print_keyed_relays<K: Serialize + fmt::Display>(
+    netdir: &tor_netdir::NetDir,
+    totals: &Totals,
+    relays: &[(K, tor_netdir::Relay)],
+    (name, title): (&str, &str),
+    format: Format,
+    columns: &Columns,
+    indent: usize,
+) -> Result<()> {
+    let rows = || -> Vec<Vec<String>> {
+        relays
+            .iter()
+            .map(|(key, r)| {
+                let mut row = vec![key.to_string()];
+                row.extend(columns.values(netdir, totals, r));
+                row
+            })
+            .collect()
+    };
+    match format {
+        Format::Text => {
+            for (key, r) in relays {
+                println!("{:indent$}[+] {}: {}", "", title, key);
+                util::describe_relays(
+                    netdir,
+                    totals,
+                    std::slice::from_ref(r),
+                    false,
+                    indent,
+                );
+            }
+        }
+        Format::Oneline => {
+            let mut titles = vec![title];
+            titles.extend(columns.titles());
+            print_rows(titles, rows(), indent);
+        }
+        Format::Json => {
+            RelayInfo::load();
+            let infos: Vec<_> = relays
+                .iter()
+                .map(|relay| KeyedRelayInfo::new(netdir, totals, name, relay))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&infos)?);
+        }
+        Format::Ndjson => {
+            RelayInfo::load();
+            for relay in relays {
+                let info = KeyedRelayInfo::new(netdir, totals, name, relay);
+                println!("{}", serde_json::to_string(&info)?);
+            }
+        }
+        Format::Csv | Format::Tsv => {
+            let delimiter = if format == Format::Csv { b',' } else { b'\t' };
+            let mut names = vec![name];
+            names.extend(columns.names());
+            print_separated(names, rows(), delimiter)?
+        }
+        Format::Fps => print_fingerprints(
+            &relays.iter().map(|(_, r)| r).collect::<Vec<_>>(),
+        ),
+    }
+    Ok(())
+}
+
+/// Print the share of the network of the relays in the given format.
+pub fn print_summary(
+    netdir: &tor_netdir::NetDir,
+    totals: &Totals,
+    relays: &[tor_netdir::Relay],
+    format: Format,
+) -> Result<()> {
+    let summary = Summary::new(netdir, totals, relays);
+    match format {
+        Format::Text | Format::Oneline => println!("[+] {summary}"),
+        // A summary has no fingerprints.
+        Format::Fps => {
+            return Err(Error::UnrecognizedFormat(format!("{format:?}")).into())
+        }
+        Format::Json => {
+            println!("{}", serde_json::to_string_pretty(&summary)?)
+        }
+        Format::Ndjson => println!("{}", serde_json::to_string(&summary)?),
+        Format::Csv | Format::Tsv => {
+            let delimiter = if format == Format::Csv { b',' } else { b'\t' };
+            let mut writer = csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .from_writer(io::stdout());
+            writer.serialize(&summary)?;
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::consensus;
+
+    #[test]
+    fn format_from_str() {
+        assert_eq!("JSON".parse::<Format>().unwrap(), Format::Json);
+        assert_eq!("ndjson".parse::<Format>().unwrap(), Format::Ndjson);
+        assert_eq!("fps".parse::<Format>().unwrap(), Format::Fps);
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn summary_formats() {
+        let netdir = consensus::test_netdir();
+        let relays: Vec<_> = netdir.relays().collect();
+        let totals = Totals::new(&netdir);
+        let summary =
+            |format| print_summary(&netdir, &totals, &relays, format);
+        assert!(summary(Format::Ndjson).is_ok());
+        assert!(summary(Format::Fps).is_err());
+    }
+
+    #[test]
+    fn columns_from_str() {
+        let columns: Columns = "nickname, RSA,policy4".parse().unwrap();
+        assert_eq!(columns.names(), vec!["nickname", "rsa", "policy4"]);
+        assert!("nickname,unknown".parse::<Columns>().is_err());
+        assert_eq!(
+            Columns::default().titles(),
+            vec!["Nickname", "Rsa", "Ed", "Version", "ORPorts"]
+        );
+    }
+
+    #[test]
+    fn relay_info_to_json() {
+        let netdir = consensus::test_netdir();
+        let relay = netdir
+            .relays()
+            .find(|r| r.rs().nickname() == "ExitOne")
+            .unwrap();
+        let totals = Totals::new(&netdir);
+        let json =
+            serde_json::to_value(RelayInfo::new(&netdir, &totals, &relay))
+                .unwrap();
+        assert_eq!(json["nickname"], "ExitOne");
+        assert_eq!(json["rsa"], "03CE4B4816FE36618D586B976F932775E2C19613");
+        assert_eq!(json["weight"], 9000);
+        assert_eq!(json["measured"], true);
+        assert_eq!(json["orports"].as_array().unwrap().len(), 2);
+        assert!(json["consensus_fraction"].as_f64().unwrap() > 0.0);
+        assert!(json["exit_probability"].as_f64().unwrap() > 0.0);
+        assert_eq!(
+            json["family"][0],
+            "5DF75E9152A63D5C5331BCD1FDB8F4499B9B1685"
+        );
+        assert!(json["flags"]
+            .as_array()
+            .unwrap()
+            .contains(&serde_json::json!("Exit")));
+    }
+
+    #[test]
+    fn relay_info_databases() {
+        asn::configure_testdata();
+        descriptors::configure_testdata();
+        let netdir = consensus::test_netdir();
+        let totals = Totals::new(&netdir);
+        let relay = netdir
+            .relays()
+            .find(|r| r.rs().nickname() == "ExitOne")
+            .unwrap();
+        let json =
+            serde_json::to_value(RelayInfo::new(&netdir, &totals, &relay))
+                .unwrap();
+        assert!(json["ases"].is_array());
+        assert_eq!(json["platform"], "Tor 0.4.8.9 on Linux");
+        assert_eq!(json["uptime"], 864000);
+        assert_eq!(
+            json["bandwidth"],
+            serde_json::json!([1073741824u64, 1073741824u64, 52428800u64])
+        );
+    }
+
+    #[test]
+    fn history_columns() {
+        history::configure_testdata();
+        let columns: Columns =
+            "nickname,firstseen,lastseen,age".parse().unwrap();
+        columns.load().unwrap();
+        let netdir = consensus::test_netdir();
+        let totals = Totals::new(&netdir);
+        let relay = netdir
+            .relays()
+            .find(|r| r.rs().nickname() == "BadOne")
+            .unwrap();
+        assert_eq!(
+            columns.values(&netdir, &totals, &relay),
+            vec![
+                "BadOne",
+                "2023-05-01T12:00:00Z",
+                "2023-05-01T12:00:00Z",
+                "0"
+            ]
+        );
+    }
+
+    #[test]
+    fn descriptor_columns() {
+        descriptors::configure_testdata();
+        let columns: Columns = "nickname,platform,uptime,bwobserved,published"
+            .parse()
+            .unwrap();
+        columns.load().unwrap();
+        let netdir = consensus::test_netdir();
+        let totals = Totals::new(&netdir);
+        let values = |nickname| {
+            let relay = netdir
+                .relays()
+                .find(|r| r.rs().nickname() == nickname)
+                .unwrap();
+            columns.values(&netdir, &totals, &relay)
+        };
+        assert_eq!(
+            values("ExitTwo"),
+            vec![
+                "ExitTwo",
+                "Tor 0.4.8.4 on FreeBSD",
+                "2days",
+                "20000000",
+                "2023-05-01T09:30:00Z"
+            ]
+        );
+        assert_eq!(values("BadOne"), vec!["BadOne", "", "", "", ""]);
+    }
+
+    #[test]
+    fn contact_columns() {
+        descriptors::configure_testdata();
+        let columns: Columns = "nickname,ciiss,email,proof".parse().unwrap();
+        let netdir = consensus::test_netdir();
+        let totals = Totals::new(&netdir);
+        let values = |nickname| {
+            let relay = netdir
+                .relays()
+                .find(|r| r.rs().nickname() == nickname)
+                .unwrap();
+            columns.values(&netdir, &totals, &relay)
+        };
+        assert_eq!(
+            values("ExitTwo"),
+            vec!["ExitTwo", "true", "admin@exitsrus.example", ""]
+        );
+        assert_eq!(values("GuardAlpha"), vec!["GuardAlpha", "false", "", ""]);
+        assert_eq!(values("BadOne"), vec!["BadOne", "", "", ""]);
+    }
+
+    #[test]
+    fn rsa_column() {
+        let netdir = consensus::test_netdir();
+        let totals = Totals::new(&netdir);
+        let relay = netdir
+            .relays()
+            .find(|r| r.rs().nickname() == "ExitOne")
+            .unwrap();
+        let columns: Columns = "rsa".parse().unwrap();
+        assert_eq!(
+            columns.values(&netdir, &totals, &relay),
+            vec!["03CE4B4816FE36618D586B976F932775E2C19613"]
+        );
+    }
+
+    #[test]
+    fn probability_columns() {
+        let netdir = consensus::test_netdir();
+        let totals = Totals::new(&netdir);
+        let relay = netdir
+            .relays()
+            .find(|r| r.rs().nickname() == "ExitTwo")
+            .unwrap();
+        let columns: Columns = "nickname,guardprob,exitprob".parse().unwrap();
+        assert_eq!(
+            columns.values(&netdir, &totals, &relay),
+            vec!["ExitTwo", "0.0000", "43.7500"]
+        );
+    }
+}
@@ -0,0 +1,563 @@
+//! Per-field similarity of relays, for `sybilhunter`.
+//!
+//! Instead of comparing the concatenation of the relay fields, which a long
+//! nickname dominates, every field is compared with its own metric. Each
+//! metric returns a distance from 0, the same values, to 1, and the distance
+//! between two relays is the weighted mean of the distances of their fields.
+
+use levenshtein::levenshtein;
+use std::collections::{BTreeSet, HashSet};
+use std::fs::read_to_string;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::commands::descriptors;
+use crate::commands::err::Error;
+use crate::commands::lsh;
+use crate::commands::util;
+
+use tor_netdir::Relay;
+use tor_netdoc::types::policy::PortPolicy;
+
+/// A relay field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Nickname,
+    /// The ORPort addresses.
+    Address,
+    /// The flag bits.
+    Flags,
+    Version,
+    /// The consensus weight.
+    Weight,
+    /// The family members' fingerprints.
+    Family,
+    /// The ports allowed by the IPv4 exit policy.
+    Policy4,
+    /// The ports allowed by the IPv6 exit policy.
+    Policy6,
+    /// The platform, from the server descriptors.
+    Platform,
+    /// The contact, from the server descriptors.
+    Contact,
+}
+
+impl FromStr for Field {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nickname" | "n" => Ok(Field::Nickname),
+            "address" | "addr" | "a" => Ok(Field::Address),
+            "flags" | "fl" => Ok(Field::Flags),
+            "version" | "v" => Ok(Field::Version),
+            "weight" | "w" => Ok(Field::Weight),
+            "family" | "fam" => Ok(Field::Family),
+            "policy4" | "policy" => Ok(Field::Policy4),
+            "policy6" => Ok(Field::Policy6),
+            "platform" => Ok(Field::Platform),
+            "contact" => Ok(Field::Contact),
+            _ => Err(Error::UnrecognizedField(s.to_string())),
+        }
+    }
+}
+
+impl Field {
+    /// The metric used when none is given.
+    fn default_metric(&self) -> Metric {
+        match self {
+            Field::Nickname | Field::Address | Field::Contact => {
+                Metric::Levenshtein
+            }
+            Field::Flags => Metric::Hamming,
+            Field::Version | Field::Platform => Metric::Exact,
+            Field::Weight => Metric::Numeric,
+            Field::Family | Field::Policy4 | Field::Policy6 => Metric::Jaccard,
+        }
+    }
+
+    fn value(&self, relay: &Relay) -> Value {
+        match self {
+            Field::Nickname => Value::Text(relay.rs().nickname().to_string()),
+            Field::Address => Value::Text(util::get_orports(relay)),
+            Field::Flags => Value::Bits(relay.rs().flags().bits() as u64),
+            Field::Version => Value::Text(util::get_version(relay)),
+            Field::Weight => Value::Number(util::get_weight(relay).0 as f64),
+            Field::Family => {
+                Value::Set(util::get_family(relay).into_iter().collect())
+            }
+            Field::Policy4 => {
+                Value::Ports(policy_ranges(&relay.ipv4_policy()))
+            }
+            Field::Policy6 => {
+                Value::Ports(policy_ranges(&relay.ipv6_policy()))
+            }
+            Field::Platform => Value::Text(
+                descriptors::relay_descriptor(relay)
+                    .and_then(|desc| desc.platform.clone())
+                    .unwrap_or_default(),
+            ),
+            Field::Contact => Value::Text(
+                descriptors::relay_descriptor(relay)
+                    .and_then(|desc| desc.contact.clone())
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+}
+
+/// A way to compare two values of a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// The edit distance, divided by the length of the longest text.
+    Levenshtein,
+    /// The Jaro-Winkler distance, favouring texts with a common prefix.
+    JaroWinkler,
+    /// The Jaccard distance of the sets, eg. of ports or family members, or
+    /// of the words of a text.
+    Jaccard,
+    /// The number of different flag bits, or characters of a text, divided
+    /// by the number of bits or characters.
+    Hamming,
+    /// 0 when the values are equal, 1 otherwise.
+    Exact,
+    /// The difference of the numbers divided by the largest one.
+    Numeric,
+}
+
+impl FromStr for Metric {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "levenshtein" | "lev" => Ok(Metric::Levenshtein),
+            "jarowinkler" | "jw" => Ok(Metric::JaroWinkler),
+            "jaccard" => Ok(Metric::Jaccard),
+            "hamming" => Ok(Metric::Hamming),
+            "exact" => Ok(Metric::Exact),
+            "numeric" => Ok(Metric::Numeric),
+            _ => Err(Error::UnrecognizedMetric(s.to_string())),
+        }
+    }
+}
+
+/// The value of a field of a relay.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    Set(BTreeSet<String>),
+    /// Sorted and disjoint port ranges.
+    Ports(Vec<(u16, u16)>),
+    Bits(u64),
+    Number(f64),
+}
+
+impl Value {
+    fn text(&self) -> String {
+        match self {
+            Value::Text(text) => text.clone(),
+            Value::Set(set) => {
+                set.iter().cloned().collect::<Vec<_>>().join(" ")
+            }
+            Value::Ports(ranges) => ranges
+                .iter()
+                .map(|(lo, hi)| format!("{lo}-{hi}"))
+                .collect::<Vec<_>>()
+                .join(","),
+            Value::Bits(bits) => format!("{bits:b}"),
+            Value::Number(n) => n.to_string(),
+        }
+    }
+
+    fn words(&self) -> BTreeSet<String> {
+        match self {
+            Value::Set(set) => set.clone(),
+            value => value
+                .text()
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|word| !word.is_empty())
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+/// Return the allowed port ranges of the policy.
+pub fn policy_ranges(policy: &PortPolicy) -> Vec<(u16, u16)> {
+    // The policy is parsed back from its summary, eg. `accept 80,443`, as
+    // [PortPolicy] doesn't expose its ranges.
+    let summary = policy.to_string();
+    let (keyword, ports) = summary.split_once(' ').unwrap_or(("reject", ""));
+    let ranges: Vec<(u16, u16)> = ports
+        .split(',')
+        .filter_map(|range| util::parse_port_range(range).ok())
+        .collect();
+    if keyword == "accept" {
+        return ranges;
+    }
+    // The complement of the rejected ranges.
+    let mut allowed = Vec::new();
+    let mut next = 1u32;
+    for (lo, hi) in ranges {
+        if (lo as u32) > next {
+            allowed.push((next as u16, lo - 1));
+        }
+        next = hi as u32 + 1;
+    }
+    if next <= u16::MAX as u32 {
+        allowed.push((next as u16, u16::MAX));
+    }
+    allowed
+}
+
+/// Return the number of ports in the ranges.
+fn port_count(ranges: &[(u16, u16)]) -> u64 {
+    ranges.iter().map(|(lo, hi)| (hi - lo) as u64 + 1).sum()
+}
+
+/// Return the number of ports in both sorted and disjoint ranges.
+fn common_ports(a: &[(u16, u16)], b: &[(u16, u16)]) -> u64 {
+    let (mut i, mut j, mut count) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        let lo = a[i].0.max(b[j].0);
+        let hi = a[i].1.min(b[j].1);
+        if lo <= hi {
+            count += (hi - lo) as u64 + 1;
+        }
+        if a[i].1 < b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    count
+}
+
+/// Return the Jaccard distance of two sets from their sizes.
+fn jaccard(a: u64, b: u64, common: u64) -> f64 {
+    let union = a + b - common;
+    if union == 0 {
+        0.0
+    } else {
+        1.0 - common as f64 / union as f64
+    }
+}
+
+impl Metric {
+    /// Return the distance between the values, from 0 to 1.
+    pub fn distance(&self, a: &Value, b: &Value) -> f64 {
+        match (self, a, b) {
+            (Metric::Levenshtein, a, b) => {
+                let (a, b) = (a.text(), b.text());
+                let len = a.chars().count().max(b.chars().count());
+                if len == 0 {
+                    return 0.0;
+                }
+                levenshtein(&a, &b) as f64 / len as f64
+            }
+            (Metric::JaroWinkler, a, b) => {
+                1.0 - strsim::jaro_winkler(&a.text(), &b.text())
+            }
+            (Metric::Jaccard, Value::Ports(a), Value::Ports(b)) => {
+                jaccard(port_count(a), port_count(b), common_ports(a, b))
+            }
+            (Metric::Jaccard, a, b) => {
+                let (a, b) = (a.words(), b.words());
+                let common = a.intersection(&b).count() as u64;
+                jaccard(a.len() as u64, b.len() as u64, common)
+            }
+            (Metric::Hamming, Value::Bits(a), Value::Bits(b)) => {
+                (a ^ b).count_ones() as f64 / u16::BITS as f64
+            }
+            (Metric::Hamming, a, b) => {
+                let (a, b) = (a.text(), b.text());
+                let len = a.chars().count().max(b.chars().count());
+                if len == 0 {
+                    return 0.0;
+                }
+                let same = a.chars().zip(b.chars()).filter(|(a, b)| a == b);
+                1.0 - same.count() as f64 / len as f64
+            }
+            (Metric::Numeric, Value::Number(a), Value::Number(b)) => {
+                let max = a.abs().max(b.abs());
+                if max == 0.0 {
+                    0.0
+                } else {
+                    (a - b).abs() / max
+                }
+            }
+            (Metric::Exact | Metric::Numeric, a, b) => {
+                if a == b {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+        }
+    }
+}
+
+/// A field compared with a metric, weighing in the relay distance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldMetric {
+    pub field: Field,
+    pub metric: Metric,
+    pub weight: f64,
+}
+
+impl FromStr for FieldMetric {
+    type Err = Error;
+
+    /// Parse `<field>[:<metric>][:<weight>]`, eg. `nickname:jw:2`,
+    /// `flags:hamming` or `family:0.5`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':').map(str::trim);
+        let field: Field = parts.next().unwrap_or_default().parse()?;
+        let mut metric = field.default_metric();
+        let mut weight = 1.0;
+        for part in parts {
+            if let Ok(w) = part.parse::<f64>() {
+                if w < 0.0 || !w.is_finite() {
+                    return Err(Error::InvalidMetric(s.to_string()));
+                }
+                weight = w;
+            } else {
+                metric = part.parse()?;
+            }
+        }
+        Ok(FieldMetric {
+            field,
+            metric,
+            weight,
+        })
+    }
+}
+
+impl FieldMetric {
+    /// Add the shingles of the value of this field, of index `field`, to the
+    /// set, so that the values close with the metric share most shingles.
+    fn shingles(
+        &self,
+        field: usize,
+        value: &Value,
+        shingles: &mut HashSet<u64>,
+    ) {
+        match (self.metric, value) {
+            (Metric::Exact, value) => {
+                shingles.insert(lsh::hash(field, value.text().as_bytes()));
+            }
+            (Metric::Jaccard, Value::Text(_)) | (_, Value::Set(_)) => {
+                for word in value.words() {
+                    shingles.insert(lsh::hash(field, word.as_bytes()));
+                }
+            }
+            (_, Value::Text(text)) => {
+                lsh::text_shingles(field, text, shingles)
+            }
+            (_, Value::Ports(ranges)) => {
+                for (lo, hi) in ranges {
+                    let range = [lo.to_le_bytes(), hi.to_le_bytes()];
+                    shingles.insert(lsh::hash(field, range.as_flattened()));
+                }
+            }
+            (_, Value::Bits(bits)) => {
+                for bit in (0..u64::BITS).filter(|bit| (bits >> bit) & 1 == 1)
+                {
+                    shingles.insert(lsh::hash(field, &bit.to_le_bytes()));
+                }
+            }
+            // Two overlapping buckets of the logarithm, so that close
+            // numbers share one.
+            (_, Value::Number(n)) => {
+                let log = n.abs().max(1.0).log2() * 4.0;
+                for (bucket, offset) in [(0u8, 0.0), (1, 0.5)] {
+                    let value = ((log + offset).floor() as i64).to_le_bytes();
+                    let bytes = [&[bucket][..], &value].concat();
+                    shingles.insert(lsh::hash(field, &bytes));
+                }
+            }
+        }
+    }
+}
+
+/// The fields compared between relays.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics(Vec<FieldMetric>);
+
+/// The fields compared when none are given.
+static DEFAULT_METRICS: &str =
+    "nickname,address,flags,version,weight,family,policy4,policy6";
+
+impl Default for Metrics {
+    fn default() -> Self {
+        DEFAULT_METRICS.parse().expect("Invalid default metrics")
+    }
+}
+
+impl FromStr for Metrics {
+    type Err = Error;
+
+    /// Parse comma separated field metrics.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let metrics = s
+            .split(',')
+            .filter(|m| !m.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<FieldMetric>, _>>()?;
+        if metrics.iter().all(|m| m.weight == 0.0) {
+            return Err(Error::InvalidMetric(s.to_string()));
+        }
+        Ok(Metrics(metrics))
+    }
+}
+
+impl Metrics {
+    /// Load the metrics from a file, one `<field> [metric] [weight]` per
+    /// line, eg. `nickname jarowinkler 2`. Empty lines and lines starting
+    /// with `#` are ignored.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let text = read_to_string(path)?;
+        let metrics: Vec<String> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(":"))
+            .collect();
+        metrics.join(",").parse()
+    }
+
+    /// Whether a field needs the server descriptors.
+    pub fn needs_descriptors(&self) -> bool {
+        self.0
+            .iter()
+            .any(|m| matches!(m.field, Field::Platform | Field::Contact))
+    }
+
+    /// Return the values of the compared fields of the relay, to compare it
+    /// with many relays without computing them again.
+    pub fn values(&self, relay: &Relay) -> Vec<Value> {
+        self.0.iter().map(|m| m.field.value(relay)).collect()
+    }
+
+    /// Return the shingles of the values of the compared fields, to find the
+    /// candidate pairs of close relays with [lsh::candidates].
+    pub fn shingles(&self, values: &[Value]) -> HashSet<u64> {
+        let mut shingles = HashSet::new();
+        for (i, (m, value)) in self.0.iter().zip(values).enumerate() {
+            if m.weight > 0.0 {
+                m.shingles(i, value, &mut shingles);
+            }
+        }
+        shingles
+    }
+
+    /// Return the weighted mean of the distances of the fields' values, from
+    /// 0 to 1.
+    pub fn distance(&self, a: &[Value], b: &[Value]) -> f64 {
+        let total: f64 = self.0.iter().map(|m| m.weight).sum();
+        let sum: f64 = self
+            .0
+            .iter()
+            .zip(a.iter().zip(b))
+            .map(|(m, (a, b))| m.weight * m.metric.distance(a, b))
+            .sum();
+        sum / total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::consensus;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn metric_distances() {
+        let d = |m: Metric, a, b| m.distance(&text(a), &text(b));
+        assert_eq!(d(Metric::Levenshtein, "relay", "relay"), 0.0);
+        assert_eq!(d(Metric::Levenshtein, "abcd", "abce"), 0.25);
+        assert_eq!(d(Metric::Levenshtein, "", ""), 0.0);
+        assert!(d(Metric::JaroWinkler, "relay01", "relay02") < 0.1);
+        assert_eq!(d(Metric::Jaccard, "a b c", "b c d"), 0.5);
+        assert_eq!(d(Metric::Hamming, "abcd", "abdd"), 0.25);
+        assert_eq!(d(Metric::Exact, "0.4.8.9", "0.4.8.9"), 0.0);
+        assert_eq!(d(Metric::Exact, "0.4.8.9", "0.4.8.10"), 1.0);
+        assert_eq!(
+            Metric::Numeric
+                .distance(&Value::Number(50.0), &Value::Number(100.0)),
+            0.5
+        );
+        assert_eq!(
+            Metric::Hamming
+                .distance(&Value::Bits(0b1010), &Value::Bits(0b0110)),
+            2.0 / 16.0
+        );
+    }
+
+    #[test]
+    fn port_ranges() {
+        let accept = PortPolicy::from_str("accept 20-23,80,443").unwrap();
+        assert_eq!(policy_ranges(&accept), [(20, 23), (80, 80), (443, 443)]);
+        let reject = PortPolicy::from_str("reject 1-24,26-65535").unwrap();
+        assert_eq!(policy_ranges(&reject), [(25, 25)]);
+        let a = Value::Ports(vec![(20, 23), (80, 80)]);
+        let b = Value::Ports(vec![(22, 25), (80, 80)]);
+        // 3 common ports out of 7.
+        assert!((Metric::Jaccard.distance(&a, &b) - 4.0 / 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn metrics_from_str() {
+        let metrics: Metrics =
+            "nickname:jw:2,flags,family:0.5".parse().unwrap();
+        assert_eq!(
+            metrics.0,
+            [
+                FieldMetric {
+                    field: Field::Nickname,
+                    metric: Metric::JaroWinkler,
+                    weight: 2.0
+                },
+                FieldMetric {
+                    field: Field::Flags,
+                    metric: Metric::Hamming,
+                    weight: 1.0
+                },
+                FieldMetric {
+                    field: Field::Family,
+                    metric: Metric::Jaccard,
+                    weight: 0.5
+                },
+            ]
+        );
+        assert!("nickname:soundex".parse::<Metrics>().is_err());
+        assert!("uptime".parse::<Metrics>().is_err());
+        assert!("nickname:0".parse::<Metrics>().is_err());
+        assert!("nickname:nan".parse::<Metrics>().is_err());
+        assert!("nickname:inf,flags".parse::<Metrics>().is_err());
+    }
+
+    #[test]
+    fn relay_distances() {
+        let netdir = consensus::test_netdir();
+        let metrics = Metrics::default();
+        let values = |nickname| {
+            let relay = netdir
+                .relays()
+                .find(|r| r.rs().nickname() == nickname)
+                .unwrap();
+            metrics.values(&relay)
+        };
+        let (exit1, exit2, guard) =
+            (values("ExitOne"), values("ExitTwo"), values("GuardBeta"));
+        assert_eq!(metrics.distance(&exit1, &exit1), 0.0);
+        // The two exits are closer to each other than to a guard.
+        assert!(
+            metrics.distance(&exit1, &exit2)
+                < metrics.distance(&exit1, &guard)
+        );
+    }
+}
@@ -5,6 +5,7 @@ use std::fmt;
 use structopt::StructOpt;
 
 use crate::commands::util;
+use crate::commands::weight::Totals;
 use crate::commands::RunnableOffline;
 
 use tor_netdoc::types::policy::PortPolicy;
@@ -122,6 +123,7 @@ impl SybilCommand {
 #[async_trait]
 impl RunnableOffline for SybilCommand {
     fn run(&self, netdir: &tor_netdir::NetDir) -> Result<()> {
+        let totals = Totals::new(netdir);
         let mut policies = MultiMap::new();
         for relay in netdir.relays() {
             policies.insert(relay.ipv4_policy().clone(), relay);
@@ -138,12 +140,12 @@ impl RunnableOffline for SybilCommand {
                     "[+] Matching Reduced Exit Policy and More: '{}'",
                     policy
                 );
-                util::describe_relays(values, true, 4);
+                util::describe_relays(netdir, &totals, values, true, 4);
             } else if self
                 .match_policy_empty(policy, &REDUCED_EXIT_POLICY_DEFAULT)
             {
                 println!("[+] Not matching Reduced Exit Policy: '{}'", policy);
-                util::describe_relays(values, true, 4);
+                util::describe_relays(netdir, &totals, values, true, 4);
             }
         }
 
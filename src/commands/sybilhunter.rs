@@ -1,44 +1,611 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use levenshtein::levenshtein;
+use rayon::prelude::*;
+use std::collections::HashSet;
 use std::fmt;
+use std::path::PathBuf;
 use structopt::StructOpt;
-use tor_netdir::NetDir;
+use tor_netdir::{NetDir, Relay};
 
+use crate::commands::descriptors::{self, Descriptors};
+use crate::commands::lsh::{self, LshParams};
+use crate::commands::output::{self, Columns, Distance, Format};
+use crate::commands::similarity::{Metrics, Value};
 use crate::commands::util;
+use crate::commands::weight::Totals;
 use crate::commands::RunnableOffline;
 
 #[derive(StructOpt)]
 pub struct SybilHunterCommand {
-    fingerprint: String,
+    #[structopt(short = "l", long = "oneline")]
+    oneline: bool,
+    /// Output format: text, oneline, json, ndjson, csv, tsv or fps. The text
+    /// format prints the compared fields of the relays.
+    #[structopt(short = "f", long = "format", default_value = "text")]
+    format: Format,
+    /// Comma separated columns for the oneline, csv and tsv formats, printed
+    /// after the distance or the cluster.
+    #[structopt(short = "c", long = "columns")]
+    columns: Option<Columns>,
+    /// The number of closest relays printed, by default 20, or of largest
+    /// clusters, by default all of them.
+    #[structopt(short = "n", long = "top")]
+    top: Option<usize>,
+    /// Only print the relays at most this distance away from the reference.
+    #[structopt(long = "max-distance", conflicts_with = "cluster")]
+    max_distance: Option<f64>,
+    /// The reference relay the distances are computed to.
+    #[structopt(required_unless = "cluster")]
+    fingerprint: Option<String>,
+    /// Cluster the whole consensus instead: relays are in the same cluster
+    /// when there is a chain of relays, each within `--threshold` of the
+    /// next one.
+    #[structopt(long = "cluster", conflicts_with = "fingerprint")]
+    cluster: bool,
+    /// The maximum distance between two linked relays, by default 3 for the
+    /// Levenshtein distance and 0.1 with `--metrics`.
+    #[structopt(long = "threshold")]
+    threshold: Option<f64>,
+    /// The minimum number of relays of the printed clusters.
+    #[structopt(long = "min-size", default_value = "2")]
+    min_size: usize,
+    /// Compare every field of the relays with its own metric instead of the
+    /// Levenshtein distance of their concatenation: comma separated
+    /// `<field>[:<metric>][:<weight>]`, eg. `nickname:jw:2,flags,family`.
+    #[structopt(long = "metrics", conflicts_with = "metrics-file")]
+    metrics: Option<Metrics>,
+    /// Read the metrics from a file, one `<field> [metric] [weight]` per
+    /// line.
+    #[structopt(long = "metrics-file", parse(from_os_str))]
+    metrics_file: Option<PathBuf>,
+    /// Compare the pairs of relays when clustering, instead of the candidate
+    /// pairs of a MinHash index. Much slower, but no link is missed. The
+    /// Levenshtein distance skips the relays whose string lengths differ by
+    /// more than the threshold, but with `--metrics` every pair is compared.
+    #[structopt(long = "exact", requires = "cluster")]
+    exact: bool,
 }
 
 impl fmt::Display for SybilHunterCommand {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.fingerprint)
+        match &self.fingerprint {
+            Some(fingerprint) => write!(f, "{}", fingerprint),
+            None => write!(f, "cluster, threshold {}", self.threshold()),
+        }
+    }
+}
+
+/// A disjoint-set forest, to merge clusters in almost constant time.
+struct UnionFind {
+    parents: Vec<usize>,
+    sizes: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(len: usize) -> Self {
+        Self {
+            parents: (0..len).collect(),
+            sizes: vec![1; len],
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            // Path halving.
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.sizes[a] < self.sizes[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parents[b] = a;
+        self.sizes[a] += self.sizes[b];
+    }
+}
+
+/// Group the `len` items linked by the pairs, and return the indexes of the
+/// items of every group of at least `min_size` items, from the largest group
+/// to the smallest.
+fn link(
+    len: usize,
+    links: &[(usize, usize)],
+    min_size: usize,
+) -> Vec<Vec<usize>> {
+    let mut sets = UnionFind::new(len);
+    for &(a, b) in links {
+        sets.union(a, b);
+    }
+    let mut clusters: Vec<Vec<usize>> = vec![Vec::new(); len];
+    for i in 0..len {
+        let root = sets.find(i);
+        clusters[root].push(i);
+    }
+    let mut clusters: Vec<_> = clusters
+        .into_iter()
+        .filter(|c| !c.is_empty() && c.len() >= min_size)
+        .collect();
+    clusters.sort_by(|a, b| b.len().cmp(&a.len()).then(a[0].cmp(&b[0])));
+    clusters
+}
+
+/// Single-linkage clustering of `keys.len()` items: two items are in the
+/// same cluster when there is a chain of items, each within `threshold` of
+/// the next one. The difference of the keys of two items must be a lower
+/// bound of their distance, eg. the lengths of strings for the Levenshtein
+/// distance, so that only the items of close keys are compared. Return the
+/// indexes of the items of every cluster of at least `min_size` items, from
+/// the largest cluster to the smallest.
+pub fn clusters<F>(
+    keys: &[f64],
+    distance: F,
+    threshold: f64,
+    min_size: usize,
+) -> Vec<Vec<usize>>
+where
+    F: Fn(usize, usize) -> f64 + Sync,
+{
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    order.sort_by(|&a, &b| keys[a].total_cmp(&keys[b]));
+
+    let distance = &distance;
+    let links: Vec<(usize, usize)> = (0..order.len())
+        .into_par_iter()
+        .flat_map_iter(|a| {
+            let i = order[a];
+            order[a + 1..]
+                .iter()
+                .take_while(move |&&j| keys[j] - keys[i] <= threshold)
+                .filter(move |&&j| distance(i, j) <= threshold)
+                .map(move |&j| (i, j))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    link(keys.len(), &links, min_size)
+}
+
+/// Single-linkage clustering as [clusters], only comparing the candidate
+/// pairs of the LSH index of the items' shingles. It may miss the links of
+/// items whose shingles are too different, but it doesn't compare every
+/// pair.
+pub fn lsh_clusters<F>(
+    items: &[HashSet<u64>],
+    distance: F,
+    threshold: f64,
+    min_size: usize,
+) -> Vec<Vec<usize>>
+where
+    F: Fn(usize, usize) -> f64 + Sync,
+{
+    let candidates: Vec<(usize, usize)> =
+        lsh::candidates(items, LshParams::default())
+            .into_iter()
+            .collect();
+    let links: Vec<(usize, usize)> = candidates
+        .into_par_iter()
+        .filter(|&(a, b)| distance(a, b) <= threshold)
+        .collect();
+    link(items.len(), &links, min_size)
+}
+
+/// How the relays are compared.
+enum Scorer {
+    /// The Levenshtein distance of the relays' strings, the concatenation of
+    /// their fields.
+    Levenshtein(Vec<Vec<String>>, Vec<String>),
+    /// The weighted distance of the relays' fields.
+    Metrics(Metrics, Vec<Vec<Value>>),
+}
+
+impl Scorer {
+    fn new(
+        metrics: Option<Metrics>,
+        relays: &[Relay],
+        descriptors: Option<&Descriptors>,
+    ) -> Self {
+        match metrics {
+            Some(metrics) => {
+                let values =
+                    relays.iter().map(|r| metrics.values(r)).collect();
+                Scorer::Metrics(metrics, values)
+            }
+            None => {
+                let fields: Vec<_> = relays
+                    .iter()
+                    .map(|r| util::relay2vec(r, descriptors))
+                    .collect();
+                let strings = fields.iter().map(|f| f.join("")).collect();
+                Scorer::Levenshtein(fields, strings)
+            }
+        }
+    }
+
+    /// Return the distance between the relays of these indexes.
+    fn distance(&self, a: usize, b: usize) -> f64 {
+        match self {
+            Scorer::Levenshtein(_, strings) => {
+                levenshtein(&strings[a], &strings[b]) as f64
+            }
+            Scorer::Metrics(metrics, values) => {
+                metrics.distance(&values[a], &values[b])
+            }
+        }
+    }
+
+    /// Return the shingles of the compared fields of the relays, see
+    /// [lsh_clusters].
+    fn shingles(&self) -> Vec<HashSet<u64>> {
+        match self {
+            Scorer::Levenshtein(fields, _) => {
+                fields.iter().map(|f| lsh::shingles(f)).collect()
+            }
+            Scorer::Metrics(metrics, values) => {
+                values.iter().map(|v| metrics.shingles(v)).collect()
+            }
+        }
+    }
+
+    /// Return the clustering keys of the relays, see [clusters]. The
+    /// metrics don't bound the distance, so their keys are all the same and
+    /// every pair is compared.
+    fn keys(&self) -> Vec<f64> {
+        match self {
+            Scorer::Levenshtein(_, strings) => {
+                strings.iter().map(|s| s.chars().count() as f64).collect()
+            }
+            Scorer::Metrics(_, values) => vec![0.0; values.len()],
+        }
+    }
+
+    /// Return the clusters of the relays, comparing every pair of close keys
+    /// when `exact` or else the candidate pairs of the LSH index.
+    fn clusters(
+        &self,
+        exact: bool,
+        threshold: f64,
+        min_size: usize,
+    ) -> Vec<Vec<usize>> {
+        let distance = |a, b| self.distance(a, b);
+        if exact {
+            clusters(&self.keys(), distance, threshold, min_size)
+        } else {
+            lsh_clusters(&self.shingles(), distance, threshold, min_size)
+        }
+    }
+}
+
+/// Return the clusters of the relays as `sybilhunter --cluster` does,
+/// comparing them with the metrics or else the Levenshtein distance of their
+/// [util::relay2vec] fields, from the largest cluster to the smallest.
+pub fn cluster(
+    relays: &[Relay],
+    metrics: Option<Metrics>,
+    descriptors: Option<&Descriptors>,
+    exact: bool,
+    threshold: f64,
+    min_size: usize,
+) -> Vec<Vec<usize>> {
+    Scorer::new(metrics, relays, descriptors)
+        .clusters(exact, threshold, min_size)
+}
+
+impl SybilHunterCommand {
+    /// Return the clustering threshold, by default 3 for the Levenshtein
+    /// distance and 0.1 with the metrics.
+    fn threshold(&self) -> f64 {
+        let metrics = self.metrics.is_some() || self.metrics_file.is_some();
+        self.threshold.unwrap_or(if metrics { 0.1 } else { 3.0 })
+    }
+
+    fn metrics(&self) -> Result<Option<Metrics>> {
+        let metrics = match &self.metrics_file {
+            Some(path) => Some(Metrics::from_file(path)?),
+            None => self.metrics.clone(),
+        };
+        if metrics.as_ref().is_some_and(Metrics::needs_descriptors) {
+            descriptors::load()?;
+        }
+        Ok(metrics)
+    }
+
+    /// Return the server descriptors compared with the relays' strings, when
+    /// some are given.
+    fn descriptors(&self) -> Result<Option<&'static Descriptors>> {
+        Ok(descriptors::is_configured()
+            .then(descriptors::load)
+            .transpose()?)
+    }
+
+    fn format(&self) -> Format {
+        if self.oneline {
+            Format::Oneline
+        } else {
+            self.format
+        }
+    }
+
+    fn run_reference(&self, netdir: &NetDir, fingerprint: &str) -> Result<()> {
+        let format = self.format();
+        let columns = self.columns.clone().unwrap_or_default();
+        columns.load()?;
+        let reference = util::id2relay(netdir, fingerprint)?;
+        let id = *reference.rsa_id();
+        // The reference is the first relay.
+        let relays: Vec<Relay> = std::iter::once(reference)
+            .chain(netdir.relays().filter(|r| *r.rsa_id() != id))
+            .collect();
+        let descriptors = self.descriptors()?;
+        let scorer = Scorer::new(self.metrics()?, &relays, descriptors);
+        if !format.is_machine() {
+            if let Scorer::Levenshtein(_, strings) = &scorer {
+                println!("Reference string: {}", strings[0]);
+            }
+            println!("[+] Computing distances...");
+        }
+        let distances: Vec<f64> = (0..relays.len())
+            .into_par_iter()
+            .map(|i| scorer.distance(0, i))
+            .collect();
+        let mut distances: Vec<_> = distances
+            .into_iter()
+            .zip(relays)
+            .filter(|(d, _)| self.max_distance.is_none_or(|max| *d <= max))
+            .collect();
+        distances.sort_by(|a, b| a.0.total_cmp(&b.0));
+        distances.truncate(self.top.unwrap_or(20));
+
+        if !format.is_machine() {
+            println!(
+                "[+] Top {} closest relays to: {}",
+                distances.len(),
+                fingerprint
+            );
+        }
+        if format == Format::Text {
+            util::print_distances(&distances, descriptors);
+            return Ok(());
+        }
+        let distances: Vec<_> = distances
+            .into_iter()
+            .map(|(d, relay)| (Distance(d), relay))
+            .collect();
+        output::print_keyed_relays(
+            netdir,
+            &Totals::new(netdir),
+            &distances,
+            ("distance", "Distance"),
+            format,
+            &columns,
+            0,
+        )
+    }
+
+    /// Return the clusters of the relays, from the largest to the smallest.
+    fn cluster_relays(
+        &self,
+        scorer: &Scorer,
+        threshold: f64,
+    ) -> Vec<Vec<usize>> {
+        let mut clusters =
+            scorer.clusters(self.exact, threshold, self.min_size);
+        if let Some(top) = self.top {
+            clusters.truncate(top);
+        }
+        clusters
+    }
+
+    fn run_cluster(&self, netdir: &NetDir) -> Result<()> {
+        let format = self.format();
+        let columns = self.columns.clone().unwrap_or_default();
+        columns.load()?;
+        let relays: Vec<Relay> = netdir.relays().collect();
+        let descriptors = self.descriptors()?;
+        let scorer = Scorer::new(self.metrics()?, &relays, descriptors);
+        let threshold = self.threshold();
+        if !format.is_machine() {
+            println!(
+                "[+] Clustering {} relays within a distance of {}...",
+                relays.len(),
+                threshold
+            );
+        }
+        let clusters = self.cluster_relays(&scorer, threshold);
+        if clusters.is_empty() && !format.is_machine() {
+            println!("[-] No clusters of at least {} relays", self.min_size);
+            return Ok(());
+        }
+        if format == Format::Text {
+            for (n, cluster) in clusters.iter().enumerate() {
+                println!("[+] Cluster {}: {} relays", n + 1, cluster.len());
+                for &i in cluster {
+                    println!(
+                        "  {}",
+                        util::relay2print(&relays[i], descriptors)
+                    );
+                }
+            }
+            return Ok(());
+        }
+        let relays = &relays;
+        let clustered: Vec<_> = clusters
+            .iter()
+            .enumerate()
+            .flat_map(|(n, cluster)| {
+                cluster.iter().map(move |&i| (n + 1, relays[i].clone()))
+            })
+            .collect();
+        output::print_keyed_relays(
+            netdir,
+            &Totals::new(netdir),
+            &clustered,
+            ("cluster", "Cluster"),
+            format,
+            &columns,
+            0,
+        )
     }
 }
 
 #[async_trait]
 impl RunnableOffline for SybilHunterCommand {
     fn run(&self, netdir: &NetDir) -> Result<()> {
-        let reference = util::id2relay(netdir, &self.fingerprint)?;
-        let reference_str = util::relay2string(&reference);
-        println!("Reference string: {}", reference_str);
-        println!("[+] Computing distances...");
-        let mut distances: Vec<_> = netdir
-            .relays()
-            .map(|relay| {
-                (
-                    levenshtein(&reference_str, &util::relay2string(&relay)),
-                    relay,
-                )
+        match (self.cluster, &self.fingerprint) {
+            (false, Some(fingerprint)) => {
+                self.run_reference(netdir, fingerprint)
+            }
+            _ => self.run_cluster(netdir),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::consensus;
+
+    fn levenshtein_clusters(
+        strings: &[&str],
+        threshold: f64,
+        min_size: usize,
+    ) -> Vec<Vec<usize>> {
+        let keys: Vec<_> = strings.iter().map(|s| s.len() as f64).collect();
+        let distance =
+            |a: usize, b: usize| levenshtein(strings[a], strings[b]) as f64;
+        clusters(&keys, distance, threshold, min_size)
+    }
+
+    #[test]
+    fn single_linkage() {
+        let strings = [
+            "relay01", "other", "relay02", "relay12", "relay123", "unique",
+            "othar",
+        ];
+        // `relay01` and `relay12` are 2 apart, but linked by `relay02`.
+        assert_eq!(
+            levenshtein_clusters(&strings, 1.0, 2),
+            [vec![0, 2, 3, 4], vec![1, 6]]
+        );
+        assert_eq!(levenshtein_clusters(&strings, 1.0, 3), [vec![0, 2, 3, 4]]);
+        assert!(levenshtein_clusters(&strings, 0.0, 2).is_empty());
+    }
+
+    #[test]
+    fn cluster_netdir() {
+        let netdir = consensus::test_netdir();
+        let relays: Vec<_> = netdir.relays().collect();
+        let scorer = Scorer::new(None, &relays, None);
+        let keys = scorer.keys();
+        let distance = |a, b| scorer.distance(a, b);
+        assert!(clusters(&keys, distance, 0.0, 2).is_empty());
+        let all = clusters(&keys, distance, 1000.0, 2);
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].len(), 7);
+
+        // Only the two exits have a single different flag, V2Dir.
+        let scorer =
+            Scorer::new(Some("flags".parse().unwrap()), &relays, None);
+        let clusters =
+            clusters(&scorer.keys(), |a, b| scorer.distance(a, b), 0.0625, 2);
+        let nicknames: Vec<_> = clusters
+            .iter()
+            .map(|c| {
+                let mut nicknames: Vec<_> = c
+                    .iter()
+                    .map(|&i| relays[i].rs().nickname().to_string())
+                    .collect();
+                nicknames.sort();
+                nicknames
             })
             .collect();
-        distances.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(nicknames, [["ExitOne", "ExitTwo"]]);
+    }
+
+    /// Build `copies` variations of every relay of the fixture consensus,
+    /// with other nicknames and addresses, as a Sybil attack would.
+    fn sybils(copies: usize) -> Vec<Vec<String>> {
+        let netdir = consensus::test_netdir();
+        let relays: Vec<_> =
+            netdir.relays().map(|r| util::relay2vec(&r, None)).collect();
+        (0..copies)
+            .flat_map(|n| {
+                relays.iter().map(move |fields| {
+                    let mut fields = fields.clone();
+                    fields[0] = format!("{}{:04}", fields[0], n);
+                    fields[1] = format!("{}{}", n % 250, fields[1]);
+                    fields
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lsh_finds_sybils() {
+        let items = sybils(3);
+        let strings: Vec<String> = items.iter().map(|f| f.join("")).collect();
+        let distance =
+            |a: usize, b: usize| levenshtein(&strings[a], &strings[b]) as f64;
+        let keys: Vec<_> =
+            strings.iter().map(|s| s.chars().count() as f64).collect();
+        let exact = clusters(&keys, distance, 3.0, 2);
+        assert_eq!(exact.len(), 7);
+        let shingles: Vec<_> =
+            items.iter().map(|f| lsh::shingles(f)).collect();
+        assert_eq!(lsh_clusters(&shingles, distance, 3.0, 2), exact);
+    }
+
+    /// Return the nicknames of the relays of the clusters found by the
+    /// command.
+    fn cluster_nicknames(args: &[&str]) -> Vec<Vec<String>> {
+        let command = SybilHunterCommand::from_iter_safe(
+            ["sybilhunter", "--cluster"].iter().chain(args),
+        )
+        .unwrap();
+        let netdir = consensus::test_netdir();
+        let relays: Vec<_> = netdir.relays().collect();
+        let scorer = Scorer::new(command.metrics().unwrap(), &relays, None);
+        command
+            .cluster_relays(&scorer, command.threshold())
+            .iter()
+            .map(|c| {
+                let mut nicknames: Vec<_> = c
+                    .iter()
+                    .map(|&i| relays[i].rs().nickname().to_string())
+                    .collect();
+                nicknames.sort();
+                nicknames
+            })
+            .collect()
+    }
+
+    #[test]
+    fn display_threshold() {
+        let display = |args: &[&str]| {
+            SybilHunterCommand::from_iter_safe(
+                ["sybilhunter", "--cluster"].iter().chain(args),
+            )
+            .unwrap()
+            .to_string()
+        };
+        assert_eq!(display(&[]), "cluster, threshold 3");
+        assert_eq!(display(&["--metrics", "flags"]), "cluster, threshold 0.1");
+        assert_eq!(display(&["--threshold", "2.5"]), "cluster, threshold 2.5");
+    }
 
-        println!("[+] Top 20 closest relays to: {}", self.fingerprint);
-        util::print_distances(distances);
-        Ok(())
+    #[test]
+    fn cluster_metrics_command() {
+        // The LSH candidates come from the flags only, as the exact
+        // clustering.
+        let args = ["--metrics", "flags", "--threshold", "0.0625"];
+        assert_eq!(cluster_nicknames(&args), [["ExitOne", "ExitTwo"]]);
+        let exact = ["--exact", "--metrics", "flags", "--threshold", "0.0625"];
+        assert_eq!(cluster_nicknames(&exact), [["ExitOne", "ExitTwo"]]);
     }
 }
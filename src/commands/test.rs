@@ -7,13 +7,14 @@ use tor_chanmgr::ChannelUsage;
 use tor_proto::circuit::CircParameters;
 use tor_rtcompat::Runtime;
 
-use crate::commands::find;
+use crate::commands::expr::{FilterArg, FilterExpr};
+use crate::commands::weight::Totals;
 use crate::commands::Runnable;
 
 #[derive(Debug, Clone, StructOpt)]
 pub struct ExtendCommand {
-    /// The filters of this command.
-    filters: Vec<find::FindFilter>,
+    /// The filter expression of this command.
+    filters: Vec<FilterArg>,
 }
 
 impl ExtendCommand {
@@ -22,9 +23,12 @@ impl ExtendCommand {
         arti_client: &arti_client::TorClient<R>,
     ) -> Result<()> {
         let mut found: bool = false;
-        let find = find::FindCommand::new(&self.filters);
+        let expr = FilterExpr::parse(&self.filters)?;
         let netdir = arti_client.dirmgr().timely_netdir().unwrap();
-        let relays_iter = netdir.relays().filter(|r| find.match_relay(r));
+        let totals = Totals::new(&netdir);
+        let relays_iter = netdir
+            .relays()
+            .filter(|r| expr.match_relay(&netdir, &totals, r));
 
         for relay in relays_iter {
             found = true;
@@ -52,7 +56,7 @@ impl ExtendCommand {
             };
         }
         if !found {
-            println!("[-] No relays matching filters: {:?}", self.filters);
+            println!("[-] No relays matching filters: {}", expr);
         }
         Ok(())
     }
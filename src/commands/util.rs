@@ -1,17 +1,58 @@
-use prettytable::format;
-use prettytable::Table;
+use regex::Regex;
 use std::fmt;
 use std::fs::read_to_string;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
+use crate::commands::asn;
+use crate::commands::descriptors::{self, Descriptors};
 use crate::commands::err::Error;
+use crate::commands::output;
+use crate::commands::weight::{self, Position, Totals};
 
 use tor_linkspec::RelayId;
+use tor_llcrypto::pk::rsa::RsaIdentity;
 use tor_netdir::{NetDir, Relay};
 use tor_netdoc::doc::netstatus;
 use tor_netdoc::types::policy::PortPolicy;
 
+/// A comparison operator in a filter key, eg. `v>=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Eq,
+    Ge,
+    Gt,
+}
+
+impl Comparison {
+    /// Split a filter key into its name and comparison operator, eg. `v>=`
+    /// into `v` and [Comparison::Ge].
+    pub fn split_key(key: &str) -> Option<(&str, Comparison)> {
+        [
+            ("<=", Comparison::Le),
+            (">=", Comparison::Ge),
+            ("<", Comparison::Lt),
+            (">", Comparison::Gt),
+            ("=", Comparison::Eq),
+        ]
+        .into_iter()
+        .find_map(|(op, cmp)| key.strip_suffix(op).map(|name| (name, cmp)))
+    }
+
+    pub fn compare<T: PartialOrd>(&self, a: &T, b: &T) -> bool {
+        match self {
+            Comparison::Lt => a < b,
+            Comparison::Le => a <= b,
+            Comparison::Eq => a == b,
+            Comparison::Ge => a >= b,
+            Comparison::Gt => a > b,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum RelayFingerprint {
     /// Rsa identity fingerprint
@@ -52,6 +93,15 @@ impl fmt::Display for RelayFingerprint {
 }
 
 impl RelayFingerprint {
+    /// Return the fingerprint as given, without changing the case of the
+    /// ed25519 ones.
+    pub fn as_str(&self) -> &str {
+        match self {
+            RelayFingerprint::Rsa(rsa) => rsa,
+            RelayFingerprint::Ed(ed) => ed,
+        }
+    }
+
     pub fn match_relay(&self, relay: &tor_netdir::Relay) -> bool {
         match self {
             RelayFingerprint::Rsa(rsa) => relay
@@ -63,6 +113,27 @@ impl RelayFingerprint {
     }
 }
 
+/// Parse a Rsa identity fingerprint, with or without a leading `$`.
+pub fn parse_rsa_id(s: &str) -> Result<RsaIdentity, Error> {
+    match s.parse::<RelayFingerprint>()? {
+        RelayFingerprint::Rsa(rsa) => RsaIdentity::from_hex(&rsa)
+            .ok_or_else(|| Error::UndecodableFingerprint(s.to_string())),
+        RelayFingerprint::Ed(_) => {
+            Err(Error::WrongFingerprintLength(s.to_string()))
+        }
+    }
+}
+
+/// Parse a port, eg. `25`, or a port range, eg. `80-443`, into its lowest
+/// and highest ports.
+pub fn parse_port_range(s: &str) -> Result<(u16, u16), Error> {
+    let (lo, hi) = s.split_once('-').unwrap_or((s, s));
+    match (lo.trim().parse(), hi.trim().parse()) {
+        (Ok(lo), Ok(hi)) if lo <= hi => Ok((lo, hi)),
+        _ => Err(Error::InvalidPortRange(s.to_string())),
+    }
+}
+
 /// Convert a port policy from a file into a [PortPolicy].
 ///
 /// It allows only one `accept` or `reject` keywords at beginning, separated
@@ -74,7 +145,7 @@ impl RelayFingerprint {
 /// It will fail in any other case.
 ///
 /// Example file content for an accept policy:
-/// ```
+/// ```text
 /// accept
 ///  20-23
 ///  43
@@ -89,6 +160,40 @@ pub fn portpolicyfile2portpolicy(path: &Path) -> Result<PortPolicy, Error> {
     Ok(portpolicy)
 }
 
+/// Convert a glob into an anchored regular expression.
+///
+/// It supports `*`, `?` and character classes such as `[a-z]` or `[!0-9]`.
+pub fn glob2regex(glob: &str) -> Result<Regex, Error> {
+    let mut re = String::from("^");
+    let mut chars = glob.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            '[' => {
+                re.push('[');
+                if chars.as_str().starts_with('!') {
+                    chars.next();
+                    re.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        re.push('\\');
+                    }
+                    re.push(c);
+                }
+                re.push(']');
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Ok(Regex::new(&re)?)
+}
+
 /// Convert fingerprints from a file into a Vector of RelayFingerprints
 ///
 pub fn fpfile2fps(path: &Path) -> Result<Vec<RelayFingerprint>, Error> {
@@ -126,7 +231,7 @@ pub fn relays2fps(relays: &[tor_netdir::Relay]) -> Vec<String> {
     found_fps
 }
 
-fn get_version(r: &tor_netdir::Relay) -> String {
+pub fn get_version(r: &tor_netdir::Relay) -> String {
     // Can't `unwrap_or` cause can't create `Version` data type
     r.rs()
         .version()
@@ -135,7 +240,7 @@ fn get_version(r: &tor_netdir::Relay) -> String {
         .to_string()
 }
 
-fn get_orports(r: &tor_netdir::Relay) -> String {
+pub fn get_orports(r: &tor_netdir::Relay) -> String {
     r.rs()
         .orport_addrs()
         .map(ToString::to_string)
@@ -143,7 +248,25 @@ fn get_orports(r: &tor_netdir::Relay) -> String {
         .join(", ")
 }
 
-fn describe_relay(r: &tor_netdir::Relay) {
+/// Return the relay's family members' Rsa fingerprints.
+pub fn get_family(r: &tor_netdir::Relay) -> Vec<String> {
+    r.md()
+        .family()
+        .members()
+        .map(|f| f.to_string().to_uppercase().replace('$', ""))
+        .collect()
+}
+
+/// Return the relay's consensus weight and whether it was measured.
+pub fn get_weight(r: &tor_netdir::Relay) -> (u32, bool) {
+    match r.rs().weight() {
+        netstatus::RelayWeight::Measured(measured) => (*measured, true),
+        netstatus::RelayWeight::Unmeasured(unmeasured) => (*unmeasured, false),
+        &_ => (0, false),
+    }
+}
+
+fn describe_relay(netdir: &NetDir, totals: &Totals, r: &tor_netdir::Relay) {
     println!("[+] Nickname: {}", r.rs().nickname());
     println!(
         "  > Fingerprint: Rsa: {}, Ed: {}",
@@ -151,82 +274,114 @@ fn describe_relay(r: &tor_netdir::Relay) {
         r.md().ed25519_id()
     );
     println!("  > Flags: {:?}", r.rs().flags());
-    println!("  > Weight: {:?}", r.rs().weight());
+    println!(
+        "  > Weight: {:?}, {:.4}% of the network",
+        r.rs().weight(),
+        weight::consensus_fraction(netdir, totals, r) * 100.0
+    );
+    let probabilities: Vec<_> = Position::ALL
+        .iter()
+        .map(|p| {
+            let probability = weight::probability(netdir, totals, r, *p);
+            format!("{} {:.4}%", p.name(), probability * 100.0)
+        })
+        .collect();
+    println!("  > Probabilities: {}", probabilities.join(", "));
     println!("  > Version: {}", get_version(r));
     println!("  > ORPort(s): {}", get_orports(r));
     println!("  > IPv4 Policy: {}", r.ipv4_policy());
     println!("  > IPv6 Policy: {}", r.ipv6_policy());
-    println!(
-        "  > Family: {}",
-        r.md()
-            .family()
-            .members()
-            .map(|f| f.to_string().to_uppercase().replace('$', ""))
-            .collect::<Vec<String>>()
-            .join(" ")
-    );
+    println!("  > Family: {}", get_family(r).join(" "));
+    if asn::get().is_some() {
+        let ases: Vec<_> =
+            asn::relay_ases(r).iter().map(ToString::to_string).collect();
+        println!("  > AS: {}", ases.join(", "));
+    }
+    if let Some(desc) = descriptors::relay_descriptor(r) {
+        println!(
+            "  > Platform: {}",
+            desc.platform.as_deref().unwrap_or_default()
+        );
+        println!(
+            "  > Published: {}, Uptime: {}",
+            humantime::format_rfc3339_seconds(desc.published),
+            desc.uptime.map_or(String::new(), |u| {
+                humantime::format_duration(Duration::from_secs(u)).to_string()
+            })
+        );
+        println!(
+            "  > Bandwidth: rate {}, burst {}, observed {}",
+            desc.bandwidth_avg, desc.bandwidth_burst, desc.bandwidth_observed
+        );
+        println!(
+            "  > Contact: {}",
+            desc.contact.as_deref().unwrap_or_default()
+        );
+        println!("  > Exit Policy: {}", desc.exit_policy.join(", "));
+    }
 }
 
 pub fn describe_relays(
+    netdir: &NetDir,
+    totals: &Totals,
     relays: &[tor_netdir::Relay],
     oneline: bool,
     indent: usize,
 ) {
-    let tfmt = format::FormatBuilder::new()
-        .column_separator('|')
-        .borders('|')
-        .separators(
-            &[
-                format::LinePosition::Top,
-                format::LinePosition::Intern,
-                format::LinePosition::Title,
-                format::LinePosition::Bottom,
-            ],
-            format::LineSeparator::new('-', '+', '+', '+'),
-        )
-        .padding(1, 1)
-        .indent(indent)
-        .build();
-    let mut table = Table::new();
-    table.set_format(tfmt);
-    table.set_titles(row!["Nickname", "Rsa", "Ed", "Version", "ORPorts"]);
-    for r in relays {
-        if oneline {
-            table.add_row(row![
-                r.rs().nickname(),
-                r.rsa_id().to_string().to_uppercase(),
-                r.md().ed25519_id().to_string(),
-                get_version(r),
-                get_orports(r),
-            ]);
-        } else {
-            describe_relay(r)
+    if oneline {
+        let columns = output::Columns::default();
+        output::print_table(netdir, totals, relays, &columns, indent);
+        return;
+    }
+    // The ASes and the descriptors are described when they were given.
+    if asn::is_configured() {
+        if let Err(e) = asn::load() {
+            println!("[-] {}", e);
         }
     }
-    if oneline {
-        table.printstd();
+    if descriptors::is_configured() {
+        if let Err(e) = descriptors::load() {
+            println!("[-] {}", e);
+        }
+    }
+    for r in relays {
+        describe_relay(netdir, totals, r)
     }
 }
 
+/// The relay flags, named as in the torspec.
+static ROUTER_FLAGS: [(&str, netstatus::RelayFlags); 13] = [
+    ("Authority", netstatus::RelayFlags::AUTHORITY),
+    ("BadExit", netstatus::RelayFlags::BAD_EXIT),
+    ("Exit", netstatus::RelayFlags::EXIT),
+    ("Fast", netstatus::RelayFlags::FAST),
+    ("Guard", netstatus::RelayFlags::GUARD),
+    ("HSDir", netstatus::RelayFlags::HSDIR),
+    ("MiddleOnly", netstatus::RelayFlags::MIDDLE_ONLY),
+    ("NoEdConsensus", netstatus::RelayFlags::NO_ED_CONSENSUS),
+    ("Running", netstatus::RelayFlags::RUNNING),
+    ("Stable", netstatus::RelayFlags::STABLE),
+    ("StaleDesc", netstatus::RelayFlags::STALE_DESC),
+    ("V2Dir", netstatus::RelayFlags::V2DIR),
+    ("Valid", netstatus::RelayFlags::VALID),
+];
+
 /// Unfortunately, the arti RelayFlags string parsing follows the torspec case
 /// sensitiveness and thus we have to remap them ourselves.
 pub fn parse_routerflag(f: &str) -> netstatus::RelayFlags {
-    match f.to_lowercase().as_str() {
-        "authority" => netstatus::RelayFlags::AUTHORITY,
-        "badexit" => netstatus::RelayFlags::BAD_EXIT,
-        "exit" => netstatus::RelayFlags::EXIT,
-        "fast" => netstatus::RelayFlags::FAST,
-        "guard" => netstatus::RelayFlags::GUARD,
-        "hsdir" => netstatus::RelayFlags::HSDIR,
-        "middleonly" => netstatus::RelayFlags::MIDDLE_ONLY,
-        "noedconsensus" => netstatus::RelayFlags::NO_ED_CONSENSUS,
-        "running" => netstatus::RelayFlags::RUNNING,
-        "stable" => netstatus::RelayFlags::STABLE,
-        "staledesc" => netstatus::RelayFlags::STALE_DESC,
-        "v2dir" => netstatus::RelayFlags::V2DIR,
-        "valid" => netstatus::RelayFlags::VALID,
-        _ => netstatus::RelayFlags::empty(),
-    }
+    ROUTER_FLAGS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(f))
+        .map_or(netstatus::RelayFlags::empty(), |(_, flag)| *flag)
+}
+
+/// Return the names of the flags, as in the torspec.
+pub fn flag_names(flags: netstatus::RelayFlags) -> Vec<&'static str> {
+    ROUTER_FLAGS
+        .iter()
+        .filter(|(_, flag)| flags.contains(*flag))
+        .map(|(name, _)| *name)
+        .collect()
 }
 
 /// Generate an String from a relay consensus weight in the form:
@@ -280,16 +435,22 @@ pub fn weight2string(relay: &Relay) -> String {
 /// - Uptime
 /// - Contact
 ///
-/// Here we do not obtain server descriptors because there isn't an easy way to
-/// obtain them from arti yet
-/// (see https://gitlab.torproject.org/tpo/core/arti/-/issues/874), so the
-/// Vector is created from the relay's network status:
+/// The Vector is created from the relay's network status:
 /// - nickname
 /// - orport_addres
 /// - flags
 /// - tor version
 /// - weight
 ///
+/// and, when server descriptors are given (see [descriptors]), from the
+/// sybilhunter fields of the relay's server descriptor:
+/// - bandwidth_avg
+/// - bandwidth_burst
+/// - operating_system
+/// - published
+/// - uptime
+/// - contact
+///
 /// We could also use:
 /// - family
 /// - ipv4_policy
@@ -298,8 +459,11 @@ pub fn weight2string(relay: &Relay) -> String {
 /// But these last ones increase considerably the time to process the
 /// strings.
 ///
-pub fn relay2vec(relay: &Relay) -> Vec<String> {
-    vec![
+pub fn relay2vec(
+    relay: &Relay,
+    descriptors: Option<&Descriptors>,
+) -> Vec<String> {
+    let mut vec = vec![
         relay.rs().nickname().to_string(),
         // IP and port will get separated by a colon
         relay
@@ -311,38 +475,66 @@ pub fn relay2vec(relay: &Relay) -> Vec<String> {
         relay.rs().flags().bits().to_string(),
         relay.rs().version().expect("Version error").to_string(),
         weight2string(relay),
-    ]
-}
-
-/// Convert a relay Vector representation to a String without any
-/// separators.
-///
-/// It is used to compare Levenshtein distances.
-///
-pub fn relay2string(relay: &Relay) -> String {
-    relay2vec(relay).join("")
+    ];
+    if let Some(desc) = descriptors.and_then(|d| d.get(relay.rsa_id())) {
+        vec.extend([
+            desc.bandwidth_avg.to_string(),
+            desc.bandwidth_burst.to_string(),
+            desc.operating_system().unwrap_or_default().to_string(),
+            humantime::format_rfc3339_seconds(desc.published).to_string(),
+            desc.uptime.unwrap_or_default().to_string(),
+            desc.contact.clone().unwrap_or_default(),
+        ]);
+    }
+    vec
 }
 
 /// Convert a relay Vector representation to a String separated by `, `.
 ///
 /// It is used to print Levenshtein distances.
 ///
-pub fn relay2print(relay: &Relay) -> String {
+pub fn relay2print(
+    relay: &Relay,
+    descriptors: Option<&Descriptors>,
+) -> String {
     [
         relay.rsa_id().to_string().replace('$', "").to_uppercase(),
-        relay2vec(relay).join(", "),
+        relay2vec(relay, descriptors).join(", "),
     ]
     .join(", ")
 }
 
-/// Print relays Levenshtein distances.
+/// Format a distance, either a whole Levenshtein distance or a fraction.
+pub fn format_distance(distance: f64) -> String {
+    if distance.fract() == 0.0 {
+        format!("{distance:.0}")
+    } else {
+        format!("{distance:.4}")
+    }
+}
+
+/// Print relays distances.
 ///
-pub fn print_distances(distances: Vec<(usize, Relay)>) {
-    println!("distance: fingerprint, nickname, ip.port, flags bits, tor version, unmeasured measured");
+pub fn print_distances(
+    distances: &[(f64, Relay)],
+    descriptors: Option<&Descriptors>,
+) {
+    print!("distance: fingerprint, nickname, ip.port, flags bits, tor version, unmeasured measured");
+    if descriptors.is_some() {
+        print!(
+            ", bandwidth avg, bandwidth burst, os, published, uptime, contact"
+        );
+    }
+    println!();
     let distances_print: Vec<_> = distances
         .iter()
-        .take(20)
-        .map(|item| format!("{}: {}", item.0, relay2print(&item.1)))
+        .map(|item| {
+            format!(
+                "{}: {}",
+                format_distance(item.0),
+                relay2print(&item.1, descriptors)
+            )
+        })
         .collect();
     println!("{}", distances_print.join("\n"));
 }
@@ -367,6 +559,33 @@ mod tests {
         PathBuf::from(env!("CARGO_MANIFEST_DIR"))
     }
 
+    #[test]
+    fn routerflag_names() {
+        let flags = parse_routerflag("badexit") | parse_routerflag("EXIT");
+        assert_eq!(flag_names(flags), vec!["BadExit", "Exit"]);
+        assert!(parse_routerflag("unknown").is_empty());
+    }
+
+    #[test]
+    fn comparison_from_key() {
+        assert_eq!(Comparison::split_key("v>="), Some(("v", Comparison::Ge)));
+        assert_eq!(Comparison::split_key("w<"), Some(("w", Comparison::Lt)));
+        assert_eq!(Comparison::split_key("v"), None);
+        assert!(Comparison::Le.compare(&1, &1));
+        assert!(!Comparison::Gt.compare(&1, &1));
+    }
+
+    #[test]
+    fn glob_to_regex() {
+        assert_eq!(glob2regex("Quintex*").unwrap().as_str(), "^Quintex.*$");
+        assert_eq!(
+            glob2regex("a?[!0-9].b").unwrap().as_str(),
+            "^a.[^0-9]\\.b$"
+        );
+        assert!(glob2regex("moria[12]").unwrap().is_match("moria2"));
+        assert!(!glob2regex("moria[12]").unwrap().is_match("moria3"));
+    }
+
     #[test]
     fn port_policy_from_file_accept() {
         let path = root().join("testdata/policy_accept.txt");
@@ -407,4 +626,22 @@ mod tests {
         let path = root().join("testdata/policy_invalid.txt");
         let _port_policy = portpolicyfile2portpolicy(&path).unwrap();
     }
+
+    #[test]
+    fn relay_fields_with_descriptors() {
+        let netdir = crate::commands::consensus::test_netdir();
+        let relay = netdir
+            .relays()
+            .find(|r| r.rs().nickname() == "ExitOne")
+            .unwrap();
+        assert_eq!(relay2vec(&relay, None).len(), 5);
+        let descriptors = descriptors::load_descriptors(
+            &root().join("testdata/server-descriptors"),
+        )
+        .unwrap();
+        let fields = relay2vec(&relay, Some(&descriptors));
+        assert_eq!(fields.len(), 11);
+        assert_eq!(fields[7], "Linux");
+        assert_eq!(fields[9], "864000");
+    }
 }
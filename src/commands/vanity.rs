@@ -0,0 +1,348 @@
+//! Relays sharing a fingerprint prefix, as brute-forced by operators wanting
+//! vanity fingerprints.
+//!
+//! A prefix of `len` characters out of an alphabet of `a` is shared by a
+//! Poisson distributed number of the `n` fingerprints, of mean `n / a^len`,
+//! so the number of prefixes expected to be shared by at least `k` relays by
+//! chance is `a^len * P(X >= k)`. The clusters expected far less than once
+//! are reported.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::fmt;
+use structopt::StructOpt;
+
+use crate::commands::output::{self, Columns, Format};
+use crate::commands::util::{self, RelayFingerprint};
+use crate::commands::weight::Totals;
+use crate::commands::RunnableOffline;
+
+use tor_netdir::{NetDir, Relay};
+
+#[derive(StructOpt)]
+pub struct VanityCommand {
+    #[structopt(short = "l", long = "oneline")]
+    oneline: bool,
+    /// Output format: text, oneline, json, ndjson, csv, tsv or fps. The
+    /// machine-readable ones print every clustered relay with its prefix.
+    #[structopt(short = "f", long = "format", default_value = "text")]
+    format: Format,
+    /// Comma separated columns for the oneline, csv and tsv formats, printed
+    /// after the prefix.
+    #[structopt(short = "c", long = "columns")]
+    columns: Option<Columns>,
+    /// Only compare the RSA fingerprints.
+    #[structopt(long = "rsa", conflicts_with = "ed")]
+    rsa: bool,
+    /// Only compare the ed25519 fingerprints.
+    #[structopt(long = "ed")]
+    ed: bool,
+    /// The minimum number of relays sharing a prefix.
+    #[structopt(long = "min-size", default_value = "2")]
+    min_size: usize,
+    /// Only report the clusters expected less than this number of times by
+    /// chance in the consensus.
+    #[structopt(long = "max-expected", default_value = "0.01")]
+    max_expected: f64,
+}
+
+impl fmt::Display for VanityCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "min-size {}", self.min_size)
+    }
+}
+
+/// Relays sharing a fingerprint prefix.
+pub struct Cluster<'a> {
+    /// The longest prefix shared by the relays.
+    pub prefix: RelayFingerprint,
+    pub relays: Vec<Relay<'a>>,
+    /// The number of clusters of as many relays sharing a prefix as long
+    /// expected by chance.
+    pub expected: f64,
+}
+
+/// Return the RSA fingerprint of the relay in uppercase hex, or its ed25519
+/// one in base64.
+fn relay_fingerprint(relay: &Relay, ed: bool) -> RelayFingerprint {
+    if ed {
+        RelayFingerprint::Ed(relay.id().to_string())
+    } else {
+        RelayFingerprint::Rsa(hex::encode_upper(relay.rsa_id().as_bytes()))
+    }
+}
+
+/// Return the number of possible characters of the fingerprint.
+fn alphabet(fingerprint: &RelayFingerprint) -> u32 {
+    match fingerprint {
+        RelayFingerprint::Rsa(_) => 16,
+        RelayFingerprint::Ed(_) => 64,
+    }
+}
+
+fn kind(fingerprint: &RelayFingerprint) -> &'static str {
+    match fingerprint {
+        RelayFingerprint::Rsa(_) => "RSA",
+        RelayFingerprint::Ed(_) => "ed25519",
+    }
+}
+
+/// Return the natural logarithm of `P(X >= k)`, for `X` Poisson distributed
+/// of mean `mean`.
+fn ln_poisson_tail(mean: f64, k: usize) -> f64 {
+    let ln_p = |i: usize| {
+        -mean + i as f64 * mean.ln()
+            - (1..=i).map(|j| (j as f64).ln()).sum::<f64>()
+    };
+    if k as f64 <= mean {
+        let below: f64 = (0..k).map(|i| ln_p(i).exp()).sum();
+        return (1.0 - below).max(0.0).ln();
+    }
+    // The terms decrease from the k-th one, so they're summed relatively to
+    // it, which doesn't underflow however unlikely it is.
+    let (mut sum, mut term, mut i) = (1.0, 1.0, k);
+    while term > f64::EPSILON * sum {
+        i += 1;
+        term *= mean / i as f64;
+        sum += term;
+    }
+    ln_p(k) + sum.ln()
+}
+
+/// Return the number of prefixes of `len` characters, out of `alphabet`
+/// possible ones, expected to be shared by at least `size` of `n` random
+/// fingerprints.
+pub fn expected_clusters(
+    n: usize,
+    alphabet: u32,
+    len: usize,
+    size: usize,
+) -> f64 {
+    let prefixes = (alphabet as f64).powi(len as i32);
+    let mean = n as f64 / prefixes;
+    (prefixes.ln() + ln_poisson_tail(mean, size)).exp()
+}
+
+/// Return the clusters of the sorted fingerprints sharing a prefix, as the
+/// range of their indexes and the length of their longest common prefix.
+fn prefix_clusters(
+    fingerprints: &[String],
+    min_size: usize,
+) -> Vec<(std::ops::Range<usize>, usize)> {
+    let max_len = fingerprints.iter().map(String::len).max().unwrap_or(0);
+    // The longer prefixes of the same relays replace the shorter ones.
+    let mut clusters: HashMap<(usize, usize), usize> = HashMap::new();
+    for len in 1..=max_len {
+        let mut start = 0;
+        while start < fingerprints.len() {
+            let first = &fingerprints[start];
+            let prefix = &first[..len.min(first.len())];
+            let end = start
+                + fingerprints[start..]
+                    .iter()
+                    .take_while(|fp| fp.starts_with(prefix))
+                    .count();
+            if end - start >= min_size.max(2) {
+                clusters.insert((start, end), len);
+            }
+            start = end;
+        }
+    }
+    clusters
+        .into_iter()
+        .map(|((start, end), len)| (start..end, len))
+        .collect()
+}
+
+/// Return the clusters of relays sharing a RSA, or ed25519, fingerprint
+/// prefix expected less than `max_expected` times by chance. The clusters
+/// within a larger reported one aren't reported.
+pub fn vanity_clusters<'a>(
+    netdir: &'a NetDir,
+    ed: bool,
+    min_size: usize,
+    max_expected: f64,
+) -> Vec<Cluster<'a>> {
+    let mut relays: Vec<(RelayFingerprint, Relay)> = netdir
+        .relays()
+        .map(|relay| (relay_fingerprint(&relay, ed), relay))
+        .collect();
+    relays.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+    let fingerprints: Vec<String> = relays
+        .iter()
+        .map(|(fp, _)| fp.as_str().to_string())
+        .collect();
+
+    let mut significant: Vec<_> = prefix_clusters(&fingerprints, min_size)
+        .into_iter()
+        .map(|(range, len)| {
+            let expected = expected_clusters(
+                relays.len(),
+                alphabet(&relays[range.start].0),
+                len,
+                range.len(),
+            );
+            (range, len, expected)
+        })
+        .filter(|(_, _, expected)| *expected < max_expected)
+        .collect();
+    // From the largest to the smallest, so the larger ones are kept first.
+    significant.sort_by_key(|(range, _, _)| std::cmp::Reverse(range.len()));
+    let mut kept: Vec<(std::ops::Range<usize>, usize, f64)> = Vec::new();
+    for cluster in significant {
+        if !kept.iter().any(|(range, _, _)| {
+            range.start <= cluster.0.start && cluster.0.end <= range.end
+        }) {
+            kept.push(cluster);
+        }
+    }
+
+    kept.into_iter()
+        .map(|(range, len, expected)| {
+            let prefix = fingerprints[range.start][..len].to_string();
+            Cluster {
+                prefix: match relays[range.start].0 {
+                    RelayFingerprint::Rsa(_) => RelayFingerprint::Rsa(prefix),
+                    RelayFingerprint::Ed(_) => RelayFingerprint::Ed(prefix),
+                },
+                relays: relays[range].iter().map(|(_, r)| r.clone()).collect(),
+                expected,
+            }
+        })
+        .collect()
+}
+
+/// Return the relays of the cluster with its prefix, to be printed by
+/// [output::print_keyed_relays].
+fn keyed_relays<'a>(cluster: &Cluster<'a>) -> Vec<(String, Relay<'a>)> {
+    let prefix = cluster.prefix.as_str();
+    cluster
+        .relays
+        .iter()
+        .map(|relay| (prefix.to_string(), relay.clone()))
+        .collect()
+}
+
+#[async_trait]
+impl RunnableOffline for VanityCommand {
+    fn run(&self, netdir: &NetDir) -> Result<()> {
+        let columns = self.columns.clone().unwrap_or_default();
+        columns.load()?;
+        let totals = Totals::new(netdir);
+        let format = if self.oneline {
+            Format::Oneline
+        } else {
+            self.format
+        };
+        let kinds: &[bool] = match (self.rsa, self.ed) {
+            (true, false) => &[false],
+            (false, true) => &[true],
+            _ => &[false, true],
+        };
+        let mut clusters: Vec<Cluster> = kinds
+            .iter()
+            .flat_map(|&ed| {
+                vanity_clusters(netdir, ed, self.min_size, self.max_expected)
+            })
+            .collect();
+        clusters.sort_by(|a, b| a.expected.total_cmp(&b.expected));
+        if format.is_machine() {
+            let relays: Vec<_> =
+                clusters.iter().flat_map(keyed_relays).collect();
+            return output::print_keyed_relays(
+                netdir,
+                &totals,
+                &relays,
+                ("prefix", "Prefix"),
+                format,
+                &columns,
+                0,
+            );
+        }
+        if clusters.is_empty() {
+            println!("[-] No fingerprint prefixes shared beyond chance");
+            return Ok(());
+        }
+        for (n, cluster) in clusters.iter().enumerate() {
+            println!(
+                "[+] Cluster {}: {} relays with the {} prefix {}, expected {:.1e} times by chance",
+                n + 1,
+                cluster.relays.len(),
+                kind(&cluster.prefix),
+                cluster.prefix.as_str(),
+                cluster.expected
+            );
+            if format == Format::Text {
+                util::describe_relays(
+                    netdir,
+                    &totals,
+                    &cluster.relays,
+                    false,
+                    2,
+                );
+                continue;
+            }
+            output::print_keyed_relays(
+                netdir,
+                &totals,
+                &keyed_relays(cluster),
+                ("prefix", "Prefix"),
+                format,
+                &columns,
+                2,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::consensus;
+
+    #[test]
+    fn expected_by_chance() {
+        // Birthday paradox: many 4 hex characters prefixes are shared by 2
+        // of 7000 relays.
+        let shared = expected_clusters(7000, 16, 4, 2);
+        assert!(shared > 300.0 && shared < 400.0, "{shared}");
+        // But 3 relays sharing 6 of them is unlikely.
+        let shared = expected_clusters(7000, 16, 6, 3);
+        assert!(shared > 1e-4 && shared < 1e-3, "{shared}");
+        // And 20 relays sharing 8 of them doesn't underflow.
+        let shared = expected_clusters(7000, 16, 8, 20);
+        assert!(shared > 0.0 && shared < 1e-100, "{shared}");
+        // Every prefix of 1 character is shared by 2 of 7000 relays.
+        let shared = expected_clusters(7000, 16, 1, 2);
+        assert!((shared - 16.0).abs() < 1e-6, "{shared}");
+    }
+
+    #[test]
+    fn longest_prefixes() {
+        let fps: Vec<String> = ["AAAA01", "AAAA02", "AAAA13", "B00000"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let mut clusters = prefix_clusters(&fps, 2);
+        clusters.sort_by_key(|(range, _)| (range.start, range.end));
+        assert_eq!(clusters, [(0..2, 5), (0..3, 4)]);
+        assert_eq!(prefix_clusters(&fps, 3), [(0..3, 4)]);
+    }
+
+    #[test]
+    fn no_vanity_in_fixture() {
+        let netdir = consensus::test_netdir();
+        assert!(vanity_clusters(&netdir, false, 2, 0.01).is_empty());
+        assert!(vanity_clusters(&netdir, true, 2, 0.01).is_empty());
+        // ExitOne and GuardAlpha share the prefix 0, as expected by chance.
+        let clusters = vanity_clusters(&netdir, false, 2, 10.0);
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].prefix.as_str(), "0");
+        assert_eq!(clusters[0].relays.len(), 2);
+        let keyed = keyed_relays(&clusters[0]);
+        assert!(keyed.iter().all(|(prefix, _)| prefix == "0"));
+        assert_eq!(keyed.len(), 2);
+    }
+}
@@ -0,0 +1,163 @@
+//! Tor versions, compared as tor does.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::commands::err::Error;
+
+/// The release status of a version, from its tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Status {
+    /// Development builds, eg. `0.4.8.0-alpha-dev`, which come before the
+    /// release they lead to.
+    Dev,
+    Alpha,
+    Beta,
+    Rc,
+    Release,
+}
+
+impl Status {
+    fn from_tag(tag: &str) -> Self {
+        match tag {
+            "" => Status::Release,
+            t if t == "dev" || t.ends_with("-dev") => Status::Dev,
+            t if t.starts_with("alpha") => Status::Alpha,
+            t if t.starts_with("beta") => Status::Beta,
+            t if t.starts_with("rc") => Status::Rc,
+            _ => Status::Dev,
+        }
+    }
+}
+
+/// A tor version in the form `major.minor.micro.patch[-tag]`.
+///
+/// Missing components count as `0` when comparing, but not when used as a
+/// prefix, so that `0.4.1` doesn't match `0.4.10`.
+#[derive(Debug, Clone)]
+pub struct TorVersion {
+    numbers: Vec<u32>,
+    status: Status,
+    tag: String,
+}
+
+impl TorVersion {
+    fn number(&self, i: usize) -> u32 {
+        self.numbers.get(i).copied().unwrap_or(0)
+    }
+
+    /// Whether `prefix` is made of the first components of this version,
+    /// and its tag, if it has one.
+    pub fn starts_with(&self, prefix: &TorVersion) -> bool {
+        self.numbers.starts_with(&prefix.numbers)
+            && (prefix.tag.is_empty() || prefix.tag == self.tag)
+    }
+}
+
+impl FromStr for TorVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Versions in the consensus can be prefixed by `Tor ` and followed
+        // by a git tag.
+        let version = s
+            .trim()
+            .trim_start_matches("Tor ")
+            .split_whitespace()
+            .next()
+            .unwrap_or_default();
+        let (numbers, tag) = version.split_once('-').unwrap_or((version, ""));
+        let numbers = numbers
+            .split('.')
+            .map(|n| n.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| Error::InvalidVersion(s.to_string()))?;
+        if numbers.len() > 4 {
+            return Err(Error::InvalidVersion(s.to_string()));
+        }
+        Ok(TorVersion {
+            numbers,
+            status: Status::from_tag(tag),
+            tag: tag.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for TorVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let numbers: Vec<_> =
+            self.numbers.iter().map(ToString::to_string).collect();
+        write!(f, "{}", numbers.join("."))?;
+        if !self.tag.is_empty() {
+            write!(f, "-{}", self.tag)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for TorVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (0..4)
+            .map(|i| self.number(i).cmp(&other.number(i)))
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+            .then(self.status.cmp(&other.status))
+            .then_with(|| self.tag.cmp(&other.tag))
+    }
+}
+
+impl PartialOrd for TorVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for TorVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for TorVersion {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> TorVersion {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn version_from_str() {
+        assert_eq!(v("Tor 0.4.8.9").to_string(), "0.4.8.9");
+        assert_eq!(
+            v("0.4.8.0-alpha-dev (git-abc)").to_string(),
+            "0.4.8.0-alpha-dev"
+        );
+        assert!("0.4.x".parse::<TorVersion>().is_err());
+        assert!("0.4.8.9.1".parse::<TorVersion>().is_err());
+        assert!("".parse::<TorVersion>().is_err());
+    }
+
+    #[test]
+    fn version_ordering() {
+        assert!(v("0.4.1.5") < v("0.4.10.1"));
+        assert!(v("0.4.8.9") < v("0.4.8.10"));
+        assert!(v("0.4.8.0-alpha-dev") < v("0.4.8.1-alpha"));
+        assert!(v("0.4.8.1-alpha") < v("0.4.8.2-beta"));
+        assert!(v("0.4.8.2-beta") < v("0.4.8.3-rc"));
+        assert!(v("0.4.8.3-rc") < v("0.4.8.3"));
+        assert!(v("0.4.8.0-alpha-dev") < v("0.4.8"));
+        assert_eq!(v("0.4.8"), v("0.4.8.0"));
+    }
+
+    #[test]
+    fn version_prefix() {
+        assert!(v("0.4.1.5").starts_with(&v("0.4.1")));
+        assert!(!v("0.4.10.1").starts_with(&v("0.4.1")));
+        assert!(v("0.4.8.1-alpha").starts_with(&v("0.4.8.1-alpha")));
+        assert!(!v("0.4.8.1").starts_with(&v("0.4.8.1-alpha")));
+    }
+}
@@ -0,0 +1,177 @@
+//! Watch the relays matching a filter expression as new consensuses arrive.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::collections::HashSet;
+use std::fmt;
+use structopt::StructOpt;
+
+use tor_llcrypto::pk::rsa::RsaIdentity;
+use tor_netdir::{DirEvent, NetDir, Relay};
+use tor_rtcompat::Runtime;
+
+use crate::commands::expr::{FilterArg, FilterExpr};
+use crate::commands::util;
+use crate::commands::weight::Totals;
+use crate::commands::Runnable;
+
+#[derive(StructOpt)]
+pub struct WatchCommand {
+    /// The filter expression of this command.
+    filters: Vec<FilterArg>,
+}
+
+impl fmt::Display for WatchCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.filters)
+    }
+}
+
+/// The relays that appeared in, and disappeared from, the relays matching
+/// the expression between two network directories.
+struct Changes<'a, 'b> {
+    appeared: Vec<Relay<'b>>,
+    disappeared: Vec<Relay<'a>>,
+}
+
+fn ids(relays: &[Relay]) -> HashSet<RsaIdentity> {
+    relays.iter().map(|relay| *relay.rsa_id()).collect()
+}
+
+fn match_changes<'a, 'b>(
+    (old, old_totals): (&'a NetDir, &Totals),
+    (new, new_totals): (&'b NetDir, &Totals),
+    expr: &FilterExpr,
+) -> Changes<'a, 'b> {
+    let old_matched = expr.filter(old, old_totals);
+    let new_matched = expr.filter(new, new_totals);
+    let (old_ids, new_ids) = (ids(&old_matched), ids(&new_matched));
+    Changes {
+        appeared: new_matched
+            .into_iter()
+            .filter(|relay| !old_ids.contains(relay.rsa_id()))
+            .collect(),
+        disappeared: old_matched
+            .into_iter()
+            .filter(|relay| !new_ids.contains(relay.rsa_id()))
+            .collect(),
+    }
+}
+
+fn valid_after(netdir: &NetDir) -> humantime::Rfc3339Timestamp {
+    humantime::format_rfc3339_seconds(netdir.lifetime().valid_after())
+}
+
+#[async_trait]
+impl<R: Runtime> Runnable<R> for WatchCommand {
+    async fn run(
+        &self,
+        arti_client: &arti_client::TorClient<R>,
+    ) -> Result<()> {
+        let expr = FilterExpr::parse(&self.filters)?;
+        let dirmgr = arti_client.dirmgr();
+        // Subscribe before taking the current directory so that no
+        // consensus is missed in between.
+        let mut events = dirmgr.events();
+        let mut netdir = dirmgr.timely_netdir()?;
+        let mut totals = Totals::new(&netdir);
+        println!(
+            "[+] {} relays matching at {}: {}",
+            expr.filter(&netdir, &totals).len(),
+            valid_after(&netdir),
+            expr
+        );
+
+        while let Some(event) = events.next().await {
+            // New microdescriptors only complete the relays of the current
+            // consensus.
+            if !matches!(event, DirEvent::NewConsensus) {
+                continue;
+            }
+            let new = match dirmgr.timely_netdir() {
+                Ok(new) => new,
+                Err(e) => {
+                    println!("[-] No usable consensus: {}", e);
+                    continue;
+                }
+            };
+            let new_totals = Totals::new(&new);
+            let changes =
+                match_changes((&netdir, &totals), (&new, &new_totals), &expr);
+            println!(
+                "[+] New consensus valid after {}: {} relays appeared, {} \
+                 disappeared",
+                valid_after(&new),
+                changes.appeared.len(),
+                changes.disappeared.len()
+            );
+            if !changes.appeared.is_empty() {
+                println!("  [+] Appeared:");
+                util::describe_relays(
+                    &new,
+                    &new_totals,
+                    &changes.appeared,
+                    true,
+                    4,
+                );
+            }
+            if !changes.disappeared.is_empty() {
+                println!("  [-] Disappeared:");
+                util::describe_relays(
+                    &netdir,
+                    &totals,
+                    &changes.disappeared,
+                    true,
+                    4,
+                );
+            }
+            netdir = new;
+            totals = new_totals;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::collector;
+    use std::path::PathBuf;
+
+    fn netdirs() -> (NetDir, NetDir) {
+        let path =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata/diff");
+        let at = humantime::parse_rfc3339_weak("2023-05-01 12:30:00").ok();
+        (
+            collector::load_netdir(&path, at).unwrap(),
+            collector::load_netdir(&path, None).unwrap(),
+        )
+    }
+
+    fn nicknames(relays: &[Relay]) -> Vec<String> {
+        relays
+            .iter()
+            .map(|r| r.rs().nickname().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn changes_of_matching_relays() {
+        let (old, new) = netdirs();
+        let old = (&old, &Totals::new(&old));
+        let new = (&new, &Totals::new(&new));
+        let args = ["fl:exit".parse().unwrap()];
+        let expr = FilterExpr::parse(&args).unwrap();
+        let changes = match_changes(old, new, &expr);
+        assert!(changes.appeared.is_empty());
+        assert_eq!(nicknames(&changes.disappeared), ["BadOne"]);
+
+        // ExitTwo upgraded.
+        let args = ["v:0.4.8.9".parse().unwrap()];
+        let expr = FilterExpr::parse(&args).unwrap();
+        let changes = match_changes(old, new, &expr);
+        assert_eq!(nicknames(&changes.appeared), ["ExitTwo"]);
+        assert!(changes.disappeared.is_empty());
+    }
+}
@@ -0,0 +1,349 @@
+//! Relay weights and their share of the whole network.
+
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::commands::err::Error;
+use crate::commands::util;
+
+use tor_netdir::{NetDir, Relay, WeightRole};
+use tor_netdoc::doc::netstatus::RelayFlags;
+
+/// The weight to compare relays with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeightKind {
+    /// The consensus weight, as voted by the bandwidth authorities.
+    Consensus,
+    /// The weight used by path selection for a position in the circuit,
+    /// which applies the consensus bandwidth-weights.
+    Role(Position),
+}
+
+impl WeightKind {
+    pub const ALL: [WeightKind; 4] = [
+        WeightKind::Consensus,
+        WeightKind::Role(Position::Guard),
+        WeightKind::Role(Position::Middle),
+        WeightKind::Role(Position::Exit),
+    ];
+}
+
+impl FromStr for WeightKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "consensus" => Ok(WeightKind::Consensus),
+            "guard" => Ok(WeightKind::Role(Position::Guard)),
+            "middle" => Ok(WeightKind::Role(Position::Middle)),
+            "exit" => Ok(WeightKind::Role(Position::Exit)),
+            _ => Err(Error::InvalidFilter(s.to_string())),
+        }
+    }
+}
+
+pub fn relay_weight(netdir: &NetDir, relay: &Relay, kind: WeightKind) -> u64 {
+    match kind {
+        WeightKind::Consensus => util::get_weight(relay).0.into(),
+        // RelayWeight doesn't expose its value, dividing it by a unit weight
+        // is the only way to get it back.
+        WeightKind::Role(position) => netdir
+            .relay_weight(relay, position.role())
+            .checked_div(1.into())
+            .map_or(0, |weight| weight as u64),
+    }
+}
+
+pub fn total_weight(netdir: &NetDir, kind: WeightKind) -> u64 {
+    netdir
+        .relays()
+        .map(|relay| relay_weight(netdir, &relay, kind))
+        .sum()
+}
+
+/// Parse a filter value in the form `[<weight kind>:]<percent>[%]`, the
+/// weight kind defaulting to the consensus weight.
+pub fn parse_percent(s: &str) -> Result<(WeightKind, f64), Error> {
+    let (kind, percent) = match s.split_once(':') {
+        Some((kind, percent)) => (kind.parse()?, percent),
+        None => (WeightKind::Consensus, s),
+    };
+    let percent = percent
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .map_err(|_| Error::InvalidFilter(s.to_string()))?;
+    Ok((kind, percent))
+}
+
+/// A position of a relay in a circuit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    Guard,
+    Middle,
+    Exit,
+}
+
+impl Position {
+    pub const ALL: [Position; 3] =
+        [Position::Guard, Position::Middle, Position::Exit];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Position::Guard => "guard",
+            Position::Middle => "middle",
+            Position::Exit => "exit",
+        }
+    }
+
+    fn role(&self) -> WeightRole {
+        match self {
+            Position::Guard => WeightRole::Guard,
+            Position::Middle => WeightRole::Middle,
+            Position::Exit => WeightRole::Exit,
+        }
+    }
+
+    /// Whether path selection would pick the relay for this position.
+    fn usable(&self, relay: &Relay) -> bool {
+        let flags = relay.rs().flags();
+        match self {
+            Position::Guard => flags.contains(RelayFlags::GUARD),
+            Position::Middle => true,
+            Position::Exit => {
+                flags.contains(RelayFlags::EXIT)
+                    && !flags.contains(RelayFlags::BAD_EXIT)
+            }
+        }
+    }
+
+    /// The weight of the relay in this position, 0 when it's not usable.
+    fn weight(&self, netdir: &NetDir, relay: &Relay) -> u64 {
+        if !self.usable(relay) {
+            return 0;
+        }
+        relay_weight(netdir, relay, WeightKind::Role(*self))
+    }
+}
+
+/// The weights of a network directory the relays are compared to.
+///
+/// They're computed once per network directory by the commands and passed
+/// down to the filters and the columns, rather than computed for every
+/// relay.
+#[derive(Debug, Clone, Default)]
+pub struct Totals {
+    /// The weights of every kind, heaviest first, each with the sum of the
+    /// weights up to it.
+    weights: [Vec<(u64, u64)>; 4],
+    /// The total weight of the relays usable in each position.
+    positions: [u64; 3],
+}
+
+impl Totals {
+    pub fn new(netdir: &NetDir) -> Self {
+        Totals {
+            weights: WeightKind::ALL.map(|kind| {
+                let mut weights: Vec<_> = netdir
+                    .relays()
+                    .map(|r| relay_weight(netdir, &r, kind))
+                    .collect();
+                weights.sort_unstable_by(|a, b| b.cmp(a));
+                weights
+                    .into_iter()
+                    .scan(0, |sum, weight| {
+                        *sum += weight;
+                        Some((weight, *sum))
+                    })
+                    .collect()
+            }),
+            positions: Position::ALL.map(|position| {
+                netdir
+                    .relays()
+                    .map(|r| position.weight(netdir, &r))
+                    .sum::<u64>()
+            }),
+        }
+    }
+
+    fn weights(&self, kind: WeightKind) -> &[(u64, u64)] {
+        let index = WeightKind::ALL.iter().position(|k| *k == kind);
+        &self.weights[index.expect("known weight kind")]
+    }
+
+    /// Return the total weight of this kind.
+    pub fn total(&self, kind: WeightKind) -> u64 {
+        self.weights(kind).last().map_or(0, |(_, sum)| *sum)
+    }
+
+    /// Return the lowest weight of the heaviest relays which together carry
+    /// `percent` of the total weight.
+    pub fn top_threshold(&self, kind: WeightKind, percent: f64) -> u64 {
+        let target = self.total(kind) as f64 * percent / 100.0;
+        if target <= 0.0 {
+            return u64::MAX;
+        }
+        let weights = self.weights(kind);
+        let index = weights.partition_point(|(_, sum)| (*sum as f64) < target);
+        weights.get(index).map_or(0, |(weight, _)| *weight)
+    }
+}
+
+fn ratio(weight: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    weight as f64 / total as f64
+}
+
+/// Return the fraction of the network consensus weight of the relay.
+pub fn consensus_fraction(
+    netdir: &NetDir,
+    totals: &Totals,
+    relay: &Relay,
+) -> f64 {
+    ratio(
+        relay_weight(netdir, relay, WeightKind::Consensus),
+        totals.total(WeightKind::Consensus),
+    )
+}
+
+/// Return the probability of the relay to be picked for this position,
+/// weighting the relays with the consensus bandwidth-weights (Wgg, Wgd, Wee,
+/// ...) as arti's path selection does.
+pub fn probability(
+    netdir: &NetDir,
+    totals: &Totals,
+    relay: &Relay,
+    position: Position,
+) -> f64 {
+    let index = Position::ALL.iter().position(|p| *p == position);
+    let total = totals.positions[index.expect("known position")];
+    ratio(position.weight(netdir, relay), total)
+}
+
+/// The share of the network of a set of relays.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub relays: usize,
+    pub consensus_weight: u64,
+    /// The fraction of the network consensus weight, from 0 to 1.
+    pub consensus_fraction: f64,
+    /// The probability of one of the relays to be picked as a guard.
+    pub guard_probability: f64,
+    pub middle_probability: f64,
+    pub exit_probability: f64,
+}
+
+impl Summary {
+    pub fn new(netdir: &NetDir, totals: &Totals, relays: &[Relay]) -> Self {
+        let sum =
+            |f: &dyn Fn(&Relay) -> f64| -> f64 { relays.iter().map(f).sum() };
+        Summary {
+            relays: relays.len(),
+            consensus_weight: relays
+                .iter()
+                .map(|r| relay_weight(netdir, r, WeightKind::Consensus))
+                .sum(),
+            consensus_fraction: sum(&|r| {
+                consensus_fraction(netdir, totals, r)
+            }),
+            guard_probability: sum(&|r| {
+                probability(netdir, totals, r, Position::Guard)
+            }),
+            middle_probability: sum(&|r| {
+                probability(netdir, totals, r, Position::Middle)
+            }),
+            exit_probability: sum(&|r| {
+                probability(netdir, totals, r, Position::Exit)
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} relays, {} consensus weight ({:.2}%), guard probability \
+             {:.2}%, middle probability {:.2}%, exit probability {:.2}%",
+            self.relays,
+            self.consensus_weight,
+            self.consensus_fraction * 100.0,
+            self.guard_probability * 100.0,
+            self.middle_probability * 100.0,
+            self.exit_probability * 100.0,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::consensus;
+
+    #[test]
+    fn percent_from_str() {
+        assert!(matches!(
+            parse_percent("1%").unwrap(),
+            (WeightKind::Consensus, p) if p == 1.0
+        ));
+        assert!(matches!(
+            parse_percent("guard:0.5").unwrap(),
+            (WeightKind::Role(Position::Guard), p) if p == 0.5
+        ));
+        assert!(parse_percent("bridge:1").is_err());
+        assert!(parse_percent("one").is_err());
+    }
+
+    #[test]
+    fn top_weight_threshold() {
+        let netdir = consensus::test_netdir();
+        let kind = WeightKind::Consensus;
+        let totals = Totals::new(&netdir);
+        assert_eq!(total_weight(&netdir, kind), 51845);
+        assert_eq!(totals.total(kind), 51845);
+        // 20000 is 38% of the total, with 15000 it's 67%.
+        assert_eq!(totals.top_threshold(kind, 50.0), 15000);
+        assert_eq!(totals.top_threshold(kind, 30.0), 20000);
+        assert_eq!(totals.top_threshold(kind, 100.0), 45);
+        assert_eq!(totals.top_threshold(kind, 0.0), u64::MAX);
+    }
+
+    #[test]
+    fn position_probabilities() {
+        let netdir = consensus::test_netdir();
+        let relays: Vec<_> = netdir.relays().collect();
+        let totals = Totals::new(&netdir);
+        let summary = Summary::new(&netdir, &totals, &relays);
+        assert_eq!(summary.relays, 7);
+        assert_eq!(summary.consensus_weight, 51845);
+        for total in [
+            summary.consensus_fraction,
+            summary.guard_probability,
+            summary.middle_probability,
+            summary.exit_probability,
+        ] {
+            assert!((total - 1.0).abs() < 1e-9);
+        }
+
+        let relay = |nickname: &str| {
+            relays
+                .iter()
+                .find(|r| r.rs().nickname() == nickname)
+                .unwrap()
+        };
+        let guard = relay("GuardAlpha");
+        let p = probability(&netdir, &totals, guard, Position::Guard);
+        assert!((p - 20000.0 / 35000.0).abs() < 1e-3);
+        assert_eq!(probability(&netdir, &totals, guard, Position::Exit), 0.0);
+        // Wme=0, exits are kept for the exit position.
+        let exit = relay("ExitOne");
+        assert_eq!(probability(&netdir, &totals, exit, Position::Middle), 0.0);
+        let p = probability(&netdir, &totals, exit, Position::Exit);
+        assert!((p - 9000.0 / 16000.0).abs() < 1e-3);
+        // Bad exits aren't picked as exits.
+        let bad = relay("BadOne");
+        assert_eq!(probability(&netdir, &totals, bad, Position::Exit), 0.0);
+    }
+}
@@ -0,0 +1,4 @@
+//! The commands of margot, also built as a library so that the benchmarks
+//! can run them.
+
+pub mod commands;
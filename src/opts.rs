@@ -1,10 +1,103 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use std::time::SystemTime;
 use structopt::StructOpt;
 
 use crate::commands;
+use crate::commands::{
+    asn, collector, consensus, descriptors, geoip, history,
+};
 
 /// Search for a pattern in a file and display the lines that contain it.
 #[derive(StructOpt)]
 pub struct Opts {
+    /// Load the consensus from this file instead of bootstrapping arti.
+    #[structopt(
+        long = "consensus",
+        parse(from_os_str),
+        requires = "microdescs"
+    )]
+    pub consensus: Option<PathBuf>,
+    /// Load the microdescriptors from this file, used with `--consensus`.
+    #[structopt(
+        long = "microdescs",
+        parse(from_os_str),
+        requires = "consensus"
+    )]
+    pub microdescs: Option<PathBuf>,
+    /// Load the consensus and microdescriptors from a CollecTor archive,
+    /// either a directory or a tarball, instead of bootstrapping arti.
+    #[structopt(
+        long = "archive",
+        parse(from_os_str),
+        conflicts_with = "consensus"
+    )]
+    pub archive: Option<PathBuf>,
+    /// Use the consensus of the archive that was valid at this time, eg.
+    /// `2023-05-01 12:30:00`. Defaults to the most recent one.
+    #[structopt(
+        long = "at",
+        parse(try_from_str = humantime::parse_rfc3339_weak),
+        requires = "archive"
+    )]
+    pub at: Option<SystemTime>,
+    /// The IPv4 GeoIP database, used by the country filter and column.
+    #[structopt(
+        long = "geoip",
+        parse(from_os_str),
+        default_value = "/usr/share/tor/geoip"
+    )]
+    pub geoip: PathBuf,
+    /// The IPv6 GeoIP database, used by the country filter and column.
+    #[structopt(
+        long = "geoip6",
+        parse(from_os_str),
+        default_value = "/usr/share/tor/geoip6"
+    )]
+    pub geoip6: PathBuf,
+    /// The IP to ASN database, eg. `ip2asn-combined.tsv` from iptoasn.com,
+    /// used by the AS filters and columns.
+    #[structopt(long = "asn-db", parse(from_os_str))]
+    pub asn_db: Option<PathBuf>,
+    /// The SQLite database of the relays history, filled by `ingest` and
+    /// used by the first/last seen filters and columns.
+    #[structopt(long = "history", parse(from_os_str))]
+    pub history: Option<PathBuf>,
+    /// Relay server descriptors, either a file, a directory or a tarball of
+    /// CollecTor files, used by the platform, uptime and bandwidth filters
+    /// and columns.
+    #[structopt(long = "descriptors", parse(from_os_str))]
+    pub descriptors: Option<PathBuf>,
     #[structopt(subcommand)]
     pub subcommand: commands::SubCommand,
 }
+
+impl Opts {
+    /// Set the paths of the databases the filters and columns may load.
+    pub fn configure(&self) {
+        geoip::configure(&self.geoip, &self.geoip6);
+        if let Some(asn_db) = &self.asn_db {
+            asn::configure(asn_db);
+        }
+        if let Some(history) = &self.history {
+            history::configure(history);
+        }
+        if let Some(descriptors) = &self.descriptors {
+            descriptors::configure(descriptors);
+        }
+    }
+
+    /// Build the network directory from the documents on disk given in the
+    /// options, if any.
+    pub fn offline_netdir(&self) -> Result<Option<tor_netdir::NetDir>> {
+        if let (Some(consensus), Some(microdescs)) =
+            (&self.consensus, &self.microdescs)
+        {
+            return Ok(Some(consensus::load_netdir(consensus, microdescs)?));
+        }
+        if let Some(archive) = &self.archive {
+            return Ok(Some(collector::load_netdir(archive, self.at)?));
+        }
+        Ok(None)
+    }
+}